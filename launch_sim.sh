@@ -0,0 +1,11 @@
+#!/usr/bin/env bash
+
+cd "$(dirname "${BASH_SOURCE[0]}")"
+export RUST_LOG=debug
+export SCANNER_PATH="$(pwd)/target/release/scanner"
+export SN_TRACER_PORT="/tmp/sn-tracer-sim"
+cargo build --release --bin scanner --bin simulator
+./target/release/simulator --link "${SN_TRACER_PORT}" $SIM_ARGS &
+SIM_PID=$!
+trap 'kill ${SIM_PID}' EXIT
+cargo run "$@" --bin sn-tracer-egui
@@ -0,0 +1,345 @@
+use std::path::PathBuf;
+#[cfg(feature = "api")]
+use std::{
+    path::{Component, Path},
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+#[cfg(feature = "api")]
+use anyhow::{anyhow, Result};
+#[cfg(feature = "gui")]
+use egui::*;
+#[cfg(feature = "api")]
+use log::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "api")]
+use serde_json::json;
+
+#[cfg(feature = "api")]
+use crate::service::Notifier;
+
+#[cfg(feature = "api")]
+/// Longest the server waits for the app to answer a request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    /// Only reachable from this machine unless changed.
+    pub address: String,
+    /// Where `POST /download` writes, the only place it can.
+    pub export_dir: PathBuf,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:8080".into(),
+            export_dir: dirs::download_dir().unwrap_or_default(),
+        }
+    }
+}
+
+impl ApiSettings {
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "Enable HTTP API");
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            ui.text_edit_singleline(&mut self.address);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Download folder");
+            let mut dir = self.export_dir.display().to_string();
+            if ui.text_edit_singleline(&mut dir).changed() {
+                self.export_dir = dir.into();
+            }
+            if ui.button("Browse").clicked() {
+                if let Some(dir) = rfd::FileDialog::new()
+                    .set_directory(&self.export_dir)
+                    .pick_folder()
+                {
+                    self.export_dir = dir;
+                }
+            }
+        });
+        ui.label("GET /records, GET /status, POST /barcode, POST /download");
+        ui.label("POST bodies must be JSON. Browser requests are refused.");
+    }
+}
+
+#[cfg(feature = "api")]
+#[derive(Debug)]
+pub enum Request {
+    Records,
+    Status,
+    /// Scans a barcode as if it came from the scanner.
+    Barcode(String),
+    /// Writes the current session to a file of this name in the export
+    /// directory, answered once it is written.
+    Download(PathBuf),
+}
+
+#[cfg(feature = "api")]
+/// Answers one request with a JSON body.
+pub struct Responder(mpsc::Sender<(u16, String)>);
+
+#[cfg(feature = "api")]
+impl Responder {
+    pub fn respond(self, status: u16, body: impl Serialize) {
+        let body = serde_json::to_string(&body).unwrap_or_else(|e| e.to_string());
+        let _ = self.0.send((status, body));
+    }
+}
+
+#[cfg(feature = "api")]
+/// Embedded HTTP server. Requests are queued for the app and answered from
+/// its state, so responses reflect what the operator sees.
+pub struct Server {
+    server: Arc<tiny_http::Server>,
+    requests: mpsc::Receiver<(Request, Responder)>,
+}
+
+#[cfg(feature = "api")]
+impl Server {
+    pub fn start(address: &str, notifier: Option<Arc<dyn Notifier>>) -> Result<Self> {
+        let server =
+            Arc::new(tiny_http::Server::http(address).map_err(|e| anyhow!("{address}: {e}"))?);
+        info!("HTTP API listening on {}", address);
+        let (send, requests) = mpsc::channel();
+        std::thread::spawn({
+            let server = server.clone();
+            move || {
+                for request in server.incoming_requests() {
+                    handle(request, &send, notifier.as_deref());
+                }
+                debug!("HTTP API stopped");
+            }
+        });
+        Ok(Self { server, requests })
+    }
+
+    pub fn try_next(&self) -> Option<(Request, Responder)> {
+        self.requests.try_recv().ok()
+    }
+}
+
+#[cfg(feature = "api")]
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+#[cfg(feature = "api")]
+#[derive(Deserialize)]
+struct BarcodeBody {
+    barcode: String,
+}
+
+#[cfg(feature = "api")]
+#[derive(Deserialize)]
+struct DownloadBody {
+    path: PathBuf,
+}
+
+#[cfg(feature = "api")]
+/// A bare file name, written straight into the export directory.
+fn export_file(path: &Path) -> Result<PathBuf, String> {
+    let mut components = path.components();
+    let file_name =
+        matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+    if !file_name {
+        return Err(format!(
+            "{path:?} must be a file name within the export directory"
+        ));
+    }
+    Ok(path.to_owned())
+}
+
+#[cfg(feature = "api")]
+fn header<'a>(request: &'a tiny_http::Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+#[cfg(feature = "api")]
+/// Web pages open in a browser on this machine can reach the API too. They
+/// send an `Origin`, and can't send JSON across origins without a CORS
+/// preflight, which is never answered.
+fn check_client(request: &tiny_http::Request) -> Result<(), (u16, String)> {
+    if header(request, "Origin").is_some() {
+        return Err((403, "Browser requests are not allowed".into()));
+    }
+    if *request.method() == tiny_http::Method::Post {
+        let json = header(request, "Content-Type")
+            .and_then(|t| t.split(';').next())
+            .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/json"));
+        if !json {
+            return Err((415, "Content-Type must be application/json".into()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "api")]
+fn parse(request: &mut tiny_http::Request) -> Result<Request, (u16, String)> {
+    use tiny_http::Method::*;
+    check_client(request)?;
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| (400, e.to_string()))?;
+    let path = request.url().split('?').next().unwrap_or_default();
+    match (request.method(), path) {
+        (Get, "/records") => Ok(Request::Records),
+        (Get, "/status") => Ok(Request::Status),
+        (Post, "/barcode") => {
+            let barcode = serde_json::from_str::<BarcodeBody>(&body)
+                .map_err(|e| (400, format!("Expected {{\"barcode\": ...}}: {e}")))?
+                .barcode;
+            match barcode.trim() {
+                "" => Err((400, "Empty barcode".into())),
+                barcode => Ok(Request::Barcode(barcode.to_string())),
+            }
+        }
+        (Post, "/download") => {
+            let path = serde_json::from_str::<DownloadBody>(&body)
+                .map_err(|e| (400, format!("Expected {{\"path\": ...}}: {e}")))?
+                .path;
+            export_file(&path)
+                .map(Request::Download)
+                .map_err(|e| (400, e))
+        }
+        (_, "/records" | "/status" | "/barcode" | "/download") => {
+            Err((405, "Method not allowed".into()))
+        }
+        _ => Err((404, "Not found".into())),
+    }
+}
+
+#[cfg(feature = "api")]
+fn handle(
+    mut request: tiny_http::Request,
+    queue: &mpsc::Sender<(Request, Responder)>,
+    notifier: Option<&dyn Notifier>,
+) {
+    debug!("HTTP {} {}", request.method(), request.url());
+    let (status, body) = match parse(&mut request) {
+        Err((status, error)) => (status, json!({ "error": error }).to_string()),
+        Ok(parsed) => {
+            let (respond, response) = mpsc::channel();
+            let queued = queue.send((parsed, Responder(respond)));
+            if let Some(notifier) = notifier {
+                notifier.notify();
+            }
+            match queued
+                .ok()
+                .and_then(|_| response.recv_timeout(RESPONSE_TIMEOUT).ok())
+            {
+                Some(response) => response,
+                None => (503, json!({ "error": "App not responding" }).to_string()),
+            }
+        }
+    };
+    let header = tiny_http::Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = tiny_http::Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        warn!("HTTP response failed: {:?}", e);
+    }
+}
+
+#[cfg(all(test, feature = "api"))]
+mod tests {
+    use super::*;
+
+    fn start() -> (Server, String) {
+        let server = Server::start("127.0.0.1:0", None).unwrap();
+        let addr = server.server.server_addr().to_ip().unwrap();
+        (server, format!("http://{addr}"))
+    }
+
+    fn status(result: Result<ureq::Response, ureq::Error>) -> u16 {
+        match result {
+            Ok(response) => response.status(),
+            Err(ureq::Error::Status(status, _)) => status,
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+
+    #[test]
+    fn export_file_stays_in_export_dir() {
+        assert!(export_file(Path::new("records.csv")).is_ok());
+        for path in [
+            "",
+            "line 1/records.xlsx",
+            "/etc/passwd",
+            "../records.csv",
+            "a/../../b.csv",
+            "./a.csv",
+        ] {
+            assert!(export_file(Path::new(path)).is_err(), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_browser_and_non_json_requests() {
+        let (_server, url) = start();
+        let barcode = format!("{url}/barcode");
+        assert_eq!(
+            status(
+                ureq::post(&barcode)
+                    .set("Content-Type", "text/plain")
+                    .send_string("123")
+            ),
+            415
+        );
+        assert_eq!(
+            status(
+                ureq::post(&barcode)
+                    .set("Origin", "https://example.com")
+                    .set("Content-Type", "application/json")
+                    .send_string(&json!({ "barcode": "123" }).to_string())
+            ),
+            403
+        );
+        assert_eq!(
+            status(
+                ureq::post(&format!("{url}/download"))
+                    .set("Content-Type", "application/json")
+                    .send_string(&json!({ "path": "/tmp/x" }).to_string())
+            ),
+            400
+        );
+    }
+
+    #[test]
+    fn queues_json_requests() {
+        let (server, url) = start();
+        let client = std::thread::spawn(move || {
+            status(
+                ureq::post(&format!("{url}/barcode"))
+                    .set("Content-Type", "application/json")
+                    .send_string(&json!({ "barcode": "123" }).to_string()),
+            )
+        });
+        let (request, responder) = loop {
+            if let Some(next) = server.try_next() {
+                break next;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert!(matches!(request, Request::Barcode(b) if b == "123"));
+        responder.respond(201, ());
+        assert_eq!(client.join().unwrap(), 201);
+    }
+}
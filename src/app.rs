@@ -0,0 +1,1863 @@
+use chrono::{DateTime, Local};
+use egui::*;
+use egui_extras::*;
+use log::*;
+use rfd::*;
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api, export, journal,
+    journal::Journal,
+    mqtt::Mqtt,
+    record::{DeviceInfo, Duplicate, DuplicateKind, Field, ReadStatus, Record, Verdict},
+    service::{self, Command, Events, Reply, ServiceHandle},
+    settings::Settings,
+    store::{self, AuditEntry, SessionSummary, Store},
+    transport::{self, Handshake, PortInfo},
+    undo::{Change, UndoStack},
+    verification,
+    webhook::Webhook,
+};
+
+const DEFAULT_SAVE_FILE: &str = "record.csv";
+
+pub struct App {
+    store: Store,
+    journal: Option<Journal>,
+    session_id: u64,
+    records: Vec<Record>,
+    text: String,
+    service: ServiceHandle,
+    events: Events,
+    operator: String,
+    operator_draft: Option<String>,
+    connection_status: ConnectionStatus,
+    download_path: Option<PathBuf>,
+    previous_connection_request: Instant,
+    keyboard: bool,
+    is_scanner_alive: bool,
+    settings: Settings,
+    settings_draft: Option<Settings>,
+    show_devices: bool,
+    ports: Option<Vec<PortInfo>>,
+    sessions: Option<Vec<SessionSummary>>,
+    store_error: Option<String>,
+    export_error: Option<String>,
+    api: Option<api::Server>,
+    api_error: Option<String>,
+    webhook: Option<Webhook>,
+    mqtt: Option<Mqtt>,
+    /// Outcome of the last scan, rejected or verified.
+    banner: Option<Banner>,
+    /// Duplicate records waiting for the operator to keep, replace or
+    /// discard them.
+    duplicates: Vec<u64>,
+    status_filter: StatusFilter,
+    /// Records to read again once the device is connected.
+    rereads: Vec<u64>,
+    row_edit: Option<RowEdit>,
+    audit_trail: Option<Vec<AuditEntry>>,
+    undo: UndoStack,
+}
+
+const AUDIT_TRAIL_LIMIT: usize = 500;
+/// The keyboard mode barcode box, which keeps focus between scans.
+const SCAN_BOX: &str = "scan_box";
+
+/// A record being edited by hand, and its earlier changes.
+struct RowEdit {
+    id: u64,
+    barcode: String,
+    note: String,
+    defect_code: String,
+    history: Vec<AuditEntry>,
+}
+
+#[derive(Debug)]
+enum Resolution {
+    Keep,
+    Replace,
+    Discard,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StatusFilter {
+    All,
+    Failed,
+    Only(ReadStatus),
+}
+
+impl StatusFilter {
+    fn all() -> impl Iterator<Item = StatusFilter> {
+        [StatusFilter::All, StatusFilter::Failed]
+            .into_iter()
+            .chain(ReadStatus::ALL.map(StatusFilter::Only))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            StatusFilter::All => "All",
+            StatusFilter::Failed => "Failed",
+            StatusFilter::Only(status) => status.name(),
+        }
+    }
+
+    fn matches(self, record: &Record) -> bool {
+        match self {
+            StatusFilter::All => true,
+            StatusFilter::Failed => record.status.is_failure(),
+            StatusFilter::Only(status) => record.status == status,
+        }
+    }
+}
+
+/// Text color of a record's row in the table.
+fn row_color(record: &Record) -> Option<Color32> {
+    match record.status {
+        ReadStatus::Timeout => Some(Color32::from_rgb(255, 140, 0)),
+        ReadStatus::NotConnected => Some(Color32::LIGHT_RED),
+        ReadStatus::ProtocolError => Some(Color32::RED),
+        _ if record.verification == Some(Verdict::Fail) => Some(Color32::RED),
+        _ if record.duplicate.is_some() => Some(Color32::GOLD),
+        _ => None,
+    }
+}
+
+struct Banner {
+    title: String,
+    detail: String,
+    color: Color32,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+struct AppStorage {
+    session_id: Option<u64>,
+    text: String,
+    operator: String,
+    download_path: Option<PathBuf>,
+
+    #[serde(default = "default_keyboard")]
+    keyboard: bool,
+
+    undo: UndoStack,
+
+    // Pre-database storage, read once to migrate.
+    #[serde(skip_serializing)]
+    records: Vec<Record>,
+    #[serde(skip_serializing)]
+    barcode_input: Vec<String>,
+    #[serde(skip_serializing)]
+    device_output: Vec<String>,
+}
+
+fn default_keyboard() -> bool {
+    false
+}
+
+impl AppStorage {
+    fn from(app: &App) -> Self {
+        Self {
+            session_id: Some(app.session_id),
+            text: app.text.clone(),
+            operator: app.operator.clone(),
+            download_path: app.download_path.clone(),
+            keyboard: app.keyboard,
+            undo: app.undo.clone(),
+            records: Vec::new(),
+            barcode_input: Vec::new(),
+            device_output: Vec::new(),
+        }
+    }
+
+    fn legacy_records(&mut self) -> Vec<Record> {
+        let mut records = std::mem::take(&mut self.records);
+        for (i, barcode) in std::mem::take(&mut self.barcode_input)
+            .into_iter()
+            .enumerate()
+        {
+            let mut record = Record::new(0, 0, barcode, Local::now(), String::new(), String::new());
+            match self.device_output.get(i).map(|o| DeviceInfo::parse(o)) {
+                Some(Ok(info)) => record.apply_read(&info, None),
+                Some(Err(e)) => record.apply_error(
+                    ReadStatus::ProtocolError,
+                    &format!("Malformed reply: {e:#}"),
+                    None,
+                ),
+                None => record.apply_error(ReadStatus::ProtocolError, "Missing reading", None),
+            }
+            records.push(record);
+        }
+        records
+    }
+
+    fn into(
+        mut self,
+        service: ServiceHandle,
+        events: Events,
+        settings: Settings,
+        store: Store,
+    ) -> App {
+        let mut app = App {
+            store,
+            journal: None,
+            session_id: 0,
+            records: Vec::new(),
+            text: std::mem::take(&mut self.text),
+            service,
+            events,
+            operator: std::mem::take(&mut self.operator),
+            operator_draft: None,
+            connection_status: ConnectionStatus::Disconnected,
+            download_path: self.download_path.take(),
+            previous_connection_request: Instant::now(),
+            keyboard: self.keyboard,
+            is_scanner_alive: true,
+            settings,
+            settings_draft: None,
+            show_devices: false,
+            ports: None,
+            sessions: None,
+            store_error: None,
+            export_error: None,
+            api: None,
+            api_error: None,
+            webhook: None,
+            mqtt: None,
+            banner: None,
+            duplicates: Vec::new(),
+            status_filter: StatusFilter::All,
+            rereads: Vec::new(),
+            row_edit: None,
+            audit_trail: None,
+            undo: std::mem::take(&mut self.undo),
+        };
+        match self
+            .session_id
+            .filter(|&id| app.store.session_exists(id).unwrap_or(false))
+        {
+            Some(id) => app.open_session(id),
+            None => app.start_session(),
+        }
+        let legacy = self.legacy_records();
+        if !legacy.is_empty() {
+            debug!("Migrating {} stored records", legacy.len());
+        }
+        for mut record in legacy {
+            record.session_id = app.session_id;
+            if let Err(e) = app.store.insert(&mut record) {
+                app.report_store_error(e);
+            }
+            app.records.push(record);
+        }
+        app
+    }
+}
+
+enum ConnectionStatus {
+    Connected(String),
+    Connecting,
+    Disconnected,
+}
+
+impl ConnectionStatus {
+    fn parts(&self) -> (&'static str, Option<&str>) {
+        match self {
+            ConnectionStatus::Connected(port) => ("Connected", Some(port)),
+            ConnectionStatus::Connecting => ("Connecting", None),
+            ConnectionStatus::Disconnected => ("Disconnected", None),
+        }
+    }
+}
+
+impl service::Notifier for egui::Context {
+    fn notify(&self) {
+        self.request_repaint();
+    }
+}
+
+fn audit_grid(ui: &mut Ui, id: &str, entries: &[AuditEntry]) {
+    Grid::new(id).striped(true).show(ui, |ui| {
+        for header in [
+            "When", "Operator", "Session", "Record", "Field", "Old", "New",
+        ] {
+            ui.label(RichText::new(header).strong());
+        }
+        ui.end_row();
+        for entry in entries {
+            ui.label(entry.at.format("%Y-%m-%d %H:%M:%S").to_string());
+            ui.label(&entry.operator);
+            ui.label(entry.session_id.to_string());
+            ui.label(entry.record_id.to_string());
+            ui.label(&entry.field);
+            ui.label(&entry.old);
+            ui.label(&entry.new);
+            ui.end_row();
+        }
+    });
+}
+
+fn show_error_dialog(title: &str, msg: &str) {
+    MessageDialog::new()
+        .set_level(MessageLevel::Error)
+        .set_title(title)
+        .set_description(msg)
+        .set_buttons(MessageButtons::Ok)
+        .show();
+}
+
+/// Whether a step is a Clear, which swaps every record shown.
+fn switches_session(step: &[Change]) -> bool {
+    step.iter().any(|c| matches!(c, Change::NewSession { .. }))
+}
+
+fn ask_confirmation(msg: &str) -> bool {
+    match MessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title("Warning")
+        .set_description(msg)
+        .set_buttons(MessageButtons::OkCancel)
+        .show()
+    {
+        MessageDialogResult::Ok => true,
+        MessageDialogResult::Cancel => false,
+        _ => false,
+    }
+}
+
+impl App {
+    // fn configure_text_styles(ctx: &egui::Context) {
+    //     use FontFamily::Proportional;
+    //     use TextStyle::*;
+
+    //     let mut style = (*ctx.style()).clone();
+    //     style.text_styles = [
+    //         (Heading, FontId::new(30.0, Proportional)),
+    //         (Body, FontId::new(18.0, Proportional)),
+    //         (Monospace, FontId::new(14.0, Proportional)),
+    //         (Button, FontId::new(14.0, Proportional)),
+    //         (Small, FontId::new(10.0, Proportional)),
+    //     ]
+    //     .into();
+    //     ctx.set_style(style);
+    // }
+
+    pub fn new(cc: &eframe::CreationContext) -> Self {
+        // Self::configure_text_styles(&cc.egui_ctx);
+        let settings = Settings::load();
+        let (service, events) = service::spawn_thread(
+            transport::from_settings(&settings.device),
+            Some(Arc::new(cc.egui_ctx.clone())),
+        );
+        let (store, on_disk) = match store::default_path().and_then(|path| Store::open(&path)) {
+            Ok(store) => (store, true),
+            Err(e) => {
+                error!("Failed to open database: {:?}", e);
+                show_error_dialog(
+                    "Database unavailable",
+                    &format!("{:?}\n\nRecords will be lost when the app closes.", e),
+                );
+                let store = Store::open_in_memory().expect("Failed to open in-memory database");
+                (store, false)
+            }
+        };
+        // Without the database the journal is left untouched, to be recovered
+        // on the next good start rather than into a throwaway database, and
+        // isn't written to, as in-memory ids would clash with the real ones.
+        let journal = if on_disk {
+            journal::default_path()
+                .and_then(|path| Journal::open(&path))
+                .and_then(|mut journal| {
+                    let recovered = journal.recover(&store)?;
+                    if recovered > 0 {
+                        info!("Recovered {} records from journal", recovered);
+                    }
+                    Ok(journal)
+                })
+                .map_err(|e| {
+                    error!("Journal unavailable: {:?}", e);
+                    show_error_dialog("Journal unavailable", &format!("{:?}", e));
+                })
+                .ok()
+        } else {
+            None
+        };
+        let mut app = match cc.storage {
+            Some(storage)
+                if eframe::get_value::<AppStorage>(storage, eframe::APP_KEY).is_some() =>
+            {
+                let app_storage: AppStorage = eframe::get_value(storage, eframe::APP_KEY).unwrap();
+                app_storage.into(service, events, settings, store)
+            }
+            _ => AppStorage::default().into(service, events, settings, store),
+        };
+        app.journal = journal;
+        app.send(Command::Connect);
+        app
+    }
+
+    fn send(&self, command: Command) {
+        if let Err(e) = self.service.send(command) {
+            error!("{:?}", e);
+        }
+    }
+
+    fn update_non_ui(&mut self) {
+        let due =
+            self.previous_connection_request.elapsed() > std::time::Duration::from_millis(200);
+        match &self.connection_status {
+            ConnectionStatus::Disconnected if due => {
+                self.previous_connection_request = Instant::now();
+                self.send(Command::Connect);
+            }
+            ConnectionStatus::Connected(_) if due => {
+                self.previous_connection_request = Instant::now();
+                self.send(Command::CheckConnection);
+            }
+            _ => {}
+        }
+        if let ConnectionStatus::Connected(_) = self.connection_status {
+            for id in std::mem::take(&mut self.rereads) {
+                self.send(Command::Read(id));
+            }
+        }
+    }
+
+    /// Reads the device again for a record, as soon as it is connected. The
+    /// record keeps its reading until a new one replaces it, which is
+    /// audited, so nothing is lost if the device fails or never answers.
+    fn reread(&mut self, id: u64) {
+        if self.rereads.contains(&id) || !self.records.iter().any(|r| r.id == id) {
+            return;
+        }
+        debug!("Re-reading record {}", id);
+        self.rereads.push(id);
+    }
+
+    fn retry_failed(&mut self) {
+        let failed: Vec<u64> = self
+            .records
+            .iter()
+            .filter(|r| r.status.is_failure())
+            .map(|r| r.id)
+            .collect();
+        for id in failed {
+            self.reread(id);
+        }
+    }
+
+    fn show_settings_window(&mut self, ctx: &egui::Context) {
+        let Some(draft) = &mut self.settings_draft else {
+            return;
+        };
+        let mut open = true;
+        let mut save = false;
+        Window::new("Settings")
+            .open(&mut open)
+            .vscroll(true)
+            .show(ctx, |ui| {
+                draft.ui(ui);
+                ui.separator();
+                ui.horizontal(|ui| {
+                    save = ui.button("Save").clicked();
+                    if ui.button("Reset to defaults").clicked() {
+                        *draft = Settings::default();
+                    }
+                });
+            });
+        if save {
+            let draft = self.settings_draft.take().unwrap();
+            self.apply_settings(draft);
+        } else if !open {
+            self.settings_draft = None;
+        }
+    }
+
+    fn show_operator_window(&mut self, ctx: &egui::Context) {
+        let Some(draft) = &mut self.operator_draft else {
+            return;
+        };
+        let mut open = true;
+        let mut operator = None;
+        Window::new("Operator")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                let input = ui.text_edit_singleline(draft);
+                let entered = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                ui.horizontal(|ui| {
+                    if ui.button("Log in").clicked() || entered {
+                        operator = Some(draft.trim().to_string());
+                    }
+                    if ui.button("Log out").clicked() {
+                        operator = Some(String::new());
+                    }
+                });
+            });
+        if let Some(operator) = operator {
+            debug!("Operator: {:?}", operator);
+            self.operator = operator;
+            self.operator_draft = None;
+        } else if !open {
+            self.operator_draft = None;
+        }
+    }
+
+    fn apply_settings(&mut self, settings: Settings) {
+        if let Err(e) = settings.save() {
+            error!("Failed to save settings: {:?}", e);
+            show_error_dialog("Settings not saved", &format!("{:?}", e));
+        }
+        if settings.device != self.settings.device {
+            self.send(Command::ApplySettings(settings.device.clone()));
+        }
+        if settings.mqtt != self.settings.mqtt || settings.station_id != self.settings.station_id {
+            self.mqtt = None;
+        }
+        if settings.api != self.settings.api {
+            self.api = None;
+            self.api_error = None;
+        }
+        self.settings = settings;
+    }
+
+    fn refresh_ports(&mut self) {
+        self.ports = None;
+        self.send(Command::ListPorts);
+    }
+
+    fn show_devices_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_devices;
+        let mut refresh = false;
+        let mut pin = None;
+        let pinned = self.settings.device.pinned_port.clone();
+        Window::new("Devices")
+            .open(&mut open)
+            .vscroll(true)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    refresh = ui.button("Refresh").clicked();
+                    match &pinned {
+                        Some(port) => {
+                            ui.label(format!("Pinned: {port}"));
+                            if ui.button("Unpin").clicked() {
+                                pin = Some(None);
+                            }
+                        }
+                        None => {
+                            ui.label("Autoconnect");
+                        }
+                    }
+                });
+                ui.separator();
+                let Some(ports) = &self.ports else {
+                    ui.spinner();
+                    return;
+                };
+                if ports.is_empty() {
+                    ui.label("No serial ports found");
+                    return;
+                }
+                Grid::new("ports").striped(true).show(ui, |ui| {
+                    for header in [
+                        "Port",
+                        "VID:PID",
+                        "Manufacturer",
+                        "Product",
+                        "Serial",
+                        "Allowed",
+                        "Handshake",
+                        "",
+                    ] {
+                        ui.label(RichText::new(header).strong());
+                    }
+                    ui.end_row();
+                    for port in ports {
+                        ui.label(&port.name);
+                        match &port.usb {
+                            Some(usb) => {
+                                ui.label(format!("{:04X}:{:04X}", usb.vid, usb.pid));
+                                ui.label(usb.manufacturer.as_deref().unwrap_or("-"));
+                                ui.label(usb.product.as_deref().unwrap_or("-"));
+                                ui.label(usb.serial_number.as_deref().unwrap_or("-"));
+                            }
+                            None => {
+                                for _ in 0..4 {
+                                    ui.label("-");
+                                }
+                            }
+                        }
+                        ui.label(if port.allowed { "✔" } else { "✖" });
+                        match &port.handshake {
+                            Handshake::Connected => {
+                                ui.colored_label(Color32::GREEN, "Connected");
+                            }
+                            Handshake::Answered => {
+                                ui.colored_label(Color32::GREEN, "Answered");
+                            }
+                            Handshake::Failed(e) => {
+                                ui.colored_label(Color32::RED, "No answer").on_hover_text(e);
+                            }
+                            Handshake::NotProbed => {
+                                ui.label("-").on_hover_text("Not probed, pin it to try it");
+                            }
+                        }
+                        if pinned.as_ref() == Some(&port.name) {
+                            if ui.button("Unpin").clicked() {
+                                pin = Some(None);
+                            }
+                        } else if ui.button("Pin").clicked() {
+                            pin = Some(Some(port.name.clone()));
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        self.show_devices = open;
+        if let Some(port) = pin {
+            let mut settings = self.settings.clone();
+            settings.device.pinned_port = port;
+            self.apply_settings(settings);
+        }
+        if refresh {
+            self.refresh_ports();
+        }
+    }
+
+    fn show_download_error_dialog(&self, msg: &str) {
+        show_error_dialog("Download failed", msg);
+    }
+
+    fn get_download_path(&self) -> Option<PathBuf> {
+        let current_path = self
+            .download_path
+            .clone()
+            .or_else(|| dirs::download_dir().map(|p| p.join(DEFAULT_SAVE_FILE)))
+            .or_else(|| std::env::current_dir().ok())?;
+        let filename = current_path.file_name()?.to_str()?;
+        let dir = current_path.parent()?;
+        FileDialog::new()
+            .add_filter("CSV", &["csv"])
+            .add_filter("Excel", &["xlsx"])
+            .set_directory(dir)
+            .set_file_name(filename)
+            .save_file()
+    }
+
+    fn start_download(&mut self, records: Vec<Record>) {
+        match self.get_download_path() {
+            None => {}
+            Some(path) => {
+                self.download_path = Some(path.clone());
+                debug!("Path set to {:?}, starting download", self.download_path);
+                self.send(Command::Download(
+                    self.download_path.as_ref().unwrap().to_owned(),
+                    records,
+                    self.settings.export.clone(),
+                ));
+            }
+        }
+    }
+
+    fn report_store_error(&mut self, e: anyhow::Error) {
+        error!("Database error: {:?}", e);
+        self.store_error = Some(e.to_string());
+    }
+
+    fn start_session(&mut self) {
+        match self
+            .store
+            .new_session(&self.settings.station_id, &self.operator)
+        {
+            Ok(id) => {
+                debug!("Started session {}", id);
+                self.session_id = id;
+                self.records.clear();
+            }
+            Err(e) => self.report_store_error(e),
+        }
+    }
+
+    fn refresh_sessions(&mut self) {
+        match self.store.sessions() {
+            Ok(sessions) => self.sessions = Some(sessions),
+            Err(e) => self.report_store_error(e),
+        }
+    }
+
+    fn show_history_window(&mut self, ctx: &egui::Context) {
+        let Some(sessions) = &self.sessions else {
+            return;
+        };
+        let mut open = true;
+        let mut reopen = None;
+        let mut export = None;
+        let mut audit_trail = false;
+        Window::new("History")
+            .open(&mut open)
+            .vscroll(true)
+            .show(ctx, |ui| {
+                audit_trail = ui.button("Audit trail").clicked();
+                Grid::new("sessions").striped(true).show(ui, |ui| {
+                    for header in [
+                        "Session",
+                        "Started",
+                        "Station",
+                        "Operator",
+                        "Records",
+                        "OK",
+                        "Mismatches",
+                    ] {
+                        ui.label(RichText::new(header).strong());
+                    }
+                    ui.end_row();
+                    for session in sessions {
+                        let current = session.id == self.session_id;
+                        let id = RichText::new(session.id.to_string());
+                        ui.label(if current { id.strong() } else { id });
+                        ui.label(session.started_at.format("%Y-%m-%d %H:%M").to_string());
+                        ui.label(&session.station);
+                        ui.label(&session.operator);
+                        ui.label(session.records.to_string());
+                        ui.label(session.ok.to_string());
+                        ui.label(session.mismatches.to_string());
+                        if ui.add_enabled(!current, Button::new("Open")).clicked() {
+                            reopen = Some(session.id);
+                        }
+                        if ui.button("Export").clicked() {
+                            export = Some(session.id);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        if !open {
+            self.sessions = None;
+        }
+        if let Some(id) = reopen {
+            self.open_session(id);
+            self.refresh_sessions();
+        }
+        if let Some(id) = export {
+            match self.store.records(id) {
+                Ok(records) => self.start_download(records),
+                Err(e) => self.report_store_error(e),
+            }
+        }
+        if audit_trail {
+            match self.store.audit_trail(None, AUDIT_TRAIL_LIMIT) {
+                Ok(entries) => self.audit_trail = Some(entries),
+                Err(e) => self.report_store_error(e),
+            }
+        }
+    }
+
+    fn open_session(&mut self, id: u64) {
+        match self.store.records(id) {
+            Ok(records) => {
+                debug!("Opened session {}", id);
+                self.session_id = id;
+                self.records = records;
+            }
+            Err(e) => self.report_store_error(e),
+        }
+    }
+
+    /// Records and reads a scanned barcode unless validation rejects it.
+    fn scan(&mut self, barcode: String, scanned_at: DateTime<Local>) -> Result<(), String> {
+        if let Err(reason) = self.settings.validation.validate(&barcode) {
+            warn!("Rejected {:?}: {}", barcode, reason);
+            self.banner = Some(Banner {
+                title: format!("✖ REJECTED {barcode}"),
+                detail: reason.clone(),
+                color: Color32::DARK_RED,
+            });
+            return Err(reason);
+        }
+        self.banner = None;
+        self.add_record(barcode, scanned_at)
+    }
+
+    /// Refuses the scan if the database can't give it an id, as any other id
+    /// could overwrite a record of another session on journal recovery.
+    fn add_record(&mut self, barcode: String, scanned_at: DateTime<Local>) -> Result<(), String> {
+        let mut record = Record::new(
+            0,
+            self.session_id,
+            barcode,
+            scanned_at,
+            self.settings.station_id.clone(),
+            self.operator.clone(),
+        );
+        record.duplicate = self.find_duplicate(&record);
+        if let Err(e) = self.store.insert(&mut record) {
+            let reason = format!("Not saved, scan again: {e}");
+            warn!("Refused {:?}: {:?}", record.barcode, e);
+            self.banner = Some(Banner {
+                title: format!("✖ NOT SAVED {}", record.barcode),
+                detail: reason.clone(),
+                color: Color32::DARK_RED,
+            });
+            self.report_store_error(e);
+            return Err(reason);
+        }
+        if record.duplicate.is_some() {
+            self.duplicates.push(record.id);
+        }
+        self.journal(&record);
+        self.stored(Ok(()));
+        self.send(Command::Read(record.id));
+        self.records.push(record);
+        Ok(())
+    }
+
+    /// The earlier record `record` repeats, if any.
+    fn find_duplicate(&mut self, record: &Record) -> Option<Duplicate> {
+        let found = self
+            .settings
+            .duplicates
+            .find(record, &self.records, &self.store);
+        match found {
+            Ok(duplicate) => {
+                if let Some(duplicate) = duplicate {
+                    warn!("{:?} duplicates record {}", record.barcode, duplicate.of);
+                }
+                duplicate
+            }
+            Err(e) => {
+                self.report_store_error(e);
+                None
+            }
+        }
+    }
+
+    /// Marks a record just read if the device gave its serial for another
+    /// one, and queues it for the operator to decide on.
+    fn check_serial_duplicate(&mut self, record: Record) -> Record {
+        if record.duplicate.is_some() {
+            return record;
+        }
+        match self.find_duplicate(&record) {
+            Some(duplicate) => {
+                self.duplicates.push(record.id);
+                self.update_record(record.id, |r| r.duplicate = Some(duplicate))
+                    .unwrap_or(record)
+            }
+            None => record,
+        }
+    }
+
+    fn find_record(&mut self, id: u64) -> Option<Record> {
+        match self.records.iter().find(|r| r.id == id) {
+            Some(record) => Some(record.clone()),
+            None => self.store.record(id).unwrap_or_else(|e| {
+                self.report_store_error(e);
+                None
+            }),
+        }
+    }
+
+    /// Deletes a record, returning it for undo.
+    fn delete_record(&mut self, id: u64) -> Option<Record> {
+        debug!("Deleting record {}", id);
+        let record = self.find_record(id);
+        if let Some(record) = &record {
+            self.audit(
+                record,
+                "Deleted",
+                record.field(Field::Barcode),
+                String::new(),
+            );
+        }
+        if let Some(Err(e)) = self.journal.as_mut().map(|j| j.delete(id)) {
+            self.report_store_error(e.context("Journal write failed"));
+        }
+        let result = self.store.delete(id);
+        self.stored(result);
+        self.records.retain(|r| r.id != id);
+        record
+    }
+
+    /// Puts a deleted record back, in its own session.
+    fn restore_record(&mut self, record: &Record) {
+        debug!("Restoring record {}", record.id);
+        self.journal(record);
+        let result = self.store.upsert(record);
+        self.stored(result);
+        if record.session_id == self.session_id {
+            let i = self.records.partition_point(|r| r.id < record.id);
+            self.records.insert(i, record.clone());
+        }
+        self.audit(
+            record,
+            "Restored",
+            String::new(),
+            record.field(Field::Barcode),
+        );
+    }
+
+    fn audit_changes(&mut self, before: &Record, after: &Record) {
+        for entry in AuditEntry::changes(before, after, &self.operator) {
+            self.record_audit(entry);
+        }
+    }
+
+    /// Starts a new session, leaving the current one in history.
+    fn clear(&mut self) {
+        let from = self.session_id;
+        self.start_session();
+        if self.session_id != from {
+            self.undo.push(vec![Change::NewSession {
+                from,
+                to: self.session_id,
+            }]);
+        }
+    }
+
+    fn undo(&mut self) {
+        if self.undo.next_undo().is_some_and(switches_session)
+            && !ask_confirmation("Undo the Clear and go back to the previous session?")
+        {
+            return;
+        }
+        if let Some(step) = self.undo.undo() {
+            debug!("Undoing {} changes", step.len());
+            for change in step.iter().rev() {
+                self.apply_change(change, true);
+            }
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.undo.next_redo().is_some_and(switches_session)
+            && !ask_confirmation("Redo the Clear and start a new session again?")
+        {
+            return;
+        }
+        if let Some(step) = self.undo.redo() {
+            debug!("Redoing {} changes", step.len());
+            for change in &step {
+                self.apply_change(change, false);
+            }
+        }
+    }
+
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        // Text fields keep Ctrl+Z for their own undo, except the scan box
+        // which is focused most of the time and has nothing worth undoing.
+        // Undoing a Clear asks first, so a stray one can't switch sessions.
+        let focus = ctx.memory(|m| m.focus());
+        if focus.is_some_and(|id| id != Id::new(SCAN_BOX)) {
+            return;
+        }
+        let (undo, redo) = ctx.input_mut(|i| {
+            let undo = i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::Z));
+            let redo = i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::Y))
+                || i.consume_shortcut(&KeyboardShortcut::new(
+                    Modifiers::COMMAND | Modifiers::SHIFT,
+                    Key::Z,
+                ));
+            (undo, redo)
+        });
+        if undo {
+            self.undo();
+        }
+        if redo {
+            self.redo();
+        }
+    }
+
+    /// Makes a change again, or reverts it if `undo`.
+    fn apply_change(&mut self, change: &Change, undo: bool) {
+        match change {
+            Change::Edit { before, after } => {
+                let (from, to) = if undo {
+                    (after, before)
+                } else {
+                    (before, after)
+                };
+                let Some(before) = self.find_record(to.id) else {
+                    return;
+                };
+                let verification = self.settings.verification.clone();
+                let after = self.update_record(to.id, |record| {
+                    record.apply_edit(from, to);
+                    // Judged again, against the reading the record has now.
+                    if record.barcode != before.barcode && record.status == ReadStatus::Ok {
+                        record.verification = verification.check(record);
+                    }
+                });
+                if let Some(after) = after {
+                    self.audit_changes(&before, &after);
+                }
+            }
+            Change::Delete(record) if undo => self.restore_record(record),
+            Change::Delete(record) => {
+                self.delete_record(record.id);
+            }
+            Change::NewSession { from, to } => self.open_session(if undo { *from } else { *to }),
+        }
+    }
+
+    fn audit(&mut self, record: &Record, field: &str, old: String, new: String) {
+        let entry = AuditEntry::new(record, &self.operator, field, old, new);
+        self.record_audit(entry);
+    }
+
+    fn record_audit(&mut self, entry: AuditEntry) {
+        info!("Audit: {:?}", entry);
+        if let Err(e) = self.store.audit(&entry) {
+            self.report_store_error(e);
+        }
+    }
+
+    fn open_row_edit(&mut self, id: u64) {
+        let Some(record) = self.find_record(id) else {
+            return;
+        };
+        let history = self
+            .store
+            .audit_trail(Some(id), AUDIT_TRAIL_LIMIT)
+            .unwrap_or_else(|e| {
+                self.report_store_error(e);
+                Vec::new()
+            });
+        self.row_edit = Some(RowEdit {
+            id,
+            barcode: record.barcode,
+            note: record.note.unwrap_or_default(),
+            defect_code: record.defect_code.unwrap_or_default(),
+            history,
+        });
+    }
+
+    /// Applies an edit and records every field it changed.
+    fn edit_record(&mut self, edit: RowEdit) {
+        let Some(before) = self.find_record(edit.id) else {
+            return;
+        };
+        let text = |s: String| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+        let barcode = text(edit.barcode);
+        let note = text(edit.note);
+        let defect_code = text(edit.defect_code);
+        let verification = self.settings.verification.clone();
+        let Some(after) = self.update_record(edit.id, |record| {
+            if let Some(barcode) = barcode.filter(|b| *b != record.barcode) {
+                record.set_barcode(barcode);
+                if record.status == ReadStatus::Ok {
+                    record.verification = verification.check(record);
+                }
+            }
+            record.note = note;
+            record.defect_code = defect_code;
+        }) else {
+            return;
+        };
+        if after != before {
+            self.audit_changes(&before, &after);
+            self.undo.push(vec![Change::edit(before, after)]);
+        }
+    }
+
+    fn show_edit_window(&mut self, ctx: &egui::Context) {
+        let Some(edit) = &mut self.row_edit else {
+            return;
+        };
+        let id = edit.id;
+        let mut open = true;
+        let mut save = false;
+        let mut delete = false;
+        Window::new(format!("Record {id}"))
+            .id(Id::new("row_edit"))
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                Grid::new("row_edit_fields").num_columns(2).show(ui, |ui| {
+                    ui.label("Barcode");
+                    ui.text_edit_singleline(&mut edit.barcode);
+                    ui.end_row();
+
+                    ui.label("Note");
+                    ui.text_edit_multiline(&mut edit.note);
+                    ui.end_row();
+
+                    ui.label("Defect code");
+                    ui.text_edit_singleline(&mut edit.defect_code);
+                    ui.end_row();
+                });
+                ui.horizontal(|ui| {
+                    save = ui.button("Save").clicked();
+                    delete = ui
+                        .add(
+                            Button::new(RichText::new("Delete").color(Color32::WHITE))
+                                .fill(Color32::DARK_RED),
+                        )
+                        .clicked();
+                });
+                if !edit.history.is_empty() {
+                    CollapsingHeader::new("History")
+                        .show(ui, |ui| audit_grid(ui, "row_history", &edit.history));
+                }
+            });
+        if save {
+            let edit = self.row_edit.take().unwrap();
+            self.edit_record(edit);
+        } else if delete && ask_confirmation(&format!("Delete record {id}?")) {
+            self.row_edit = None;
+            let deleted = self.delete_record(id);
+            self.undo
+                .push(deleted.map(Change::delete).into_iter().collect());
+        } else if !open {
+            self.row_edit = None;
+        }
+    }
+
+    fn show_audit_window(&mut self, ctx: &egui::Context) {
+        let Some(entries) = &self.audit_trail else {
+            return;
+        };
+        let mut open = true;
+        Window::new("Audit trail")
+            .open(&mut open)
+            .vscroll(true)
+            .show(ctx, |ui| {
+                if entries.is_empty() {
+                    ui.label("No records have been changed by hand");
+                }
+                audit_grid(ui, "audit_trail", entries);
+            });
+        if !open {
+            self.audit_trail = None;
+        }
+    }
+
+    fn show_duplicate_window(&mut self, ctx: &egui::Context) {
+        let Some(&id) = self.duplicates.first() else {
+            return;
+        };
+        let record = self.records.iter().find(|r| r.id == id).cloned();
+        let Some((record, duplicate)) = record.and_then(|r| r.duplicate.map(|d| (r, d))) else {
+            self.duplicates.remove(0);
+            return;
+        };
+        let earlier = self.find_record(duplicate.of);
+        let mut resolution = None;
+        Window::new("Duplicate")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(
+                    RichText::new(match duplicate.kind {
+                        DuplicateKind::Barcode => {
+                            format!("{} was scanned before", record.field(Field::Barcode))
+                        }
+                        DuplicateKind::Serial => format!(
+                            "Device serial {} was read before",
+                            record.field(Field::SerialHex)
+                        ),
+                    })
+                    .heading()
+                    .color(Color32::GOLD),
+                );
+                match &earlier {
+                    Some(earlier) => ui.label(format!(
+                        "Record {} in session {}, barcode {} scanned {}",
+                        earlier.id,
+                        earlier.session_id,
+                        earlier.field(Field::Barcode),
+                        earlier.field(Field::ScanTime),
+                    )),
+                    None => ui.label(format!("Record {} no longer exists", duplicate.of)),
+                };
+                if self.duplicates.len() > 1 {
+                    ui.label(format!("{} more waiting", self.duplicates.len() - 1));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Keep both").clicked() {
+                        resolution = Some(Resolution::Keep);
+                    }
+                    if ui
+                        .add_enabled(earlier.is_some(), Button::new("Replace earlier"))
+                        .clicked()
+                    {
+                        resolution = Some(Resolution::Replace);
+                    }
+                    if ui.button("Discard this one").clicked() {
+                        resolution = Some(Resolution::Discard);
+                    }
+                });
+            });
+        let Some(resolution) = resolution else {
+            return;
+        };
+        debug!("Duplicate {}: {:?}", id, resolution);
+        self.duplicates.remove(0);
+        match resolution {
+            Resolution::Keep => {}
+            Resolution::Replace => {
+                let mut step: Vec<Change> = self
+                    .delete_record(duplicate.of)
+                    .map(Change::delete)
+                    .into_iter()
+                    .collect();
+                if let Some(after) = self.update_record(id, |record| record.duplicate = None) {
+                    step.push(Change::edit(record, after));
+                }
+                self.undo.push(step);
+            }
+            Resolution::Discard => {
+                let deleted = self.delete_record(id);
+                self.undo
+                    .push(deleted.map(Change::delete).into_iter().collect());
+            }
+        }
+    }
+
+    fn mismatches(&self) -> usize {
+        self.records
+            .iter()
+            .filter(|r| r.verification == Some(Verdict::Fail))
+            .count()
+    }
+
+    fn connected_port(&self) -> Option<String> {
+        match &self.connection_status {
+            ConnectionStatus::Connected(port) => Some(port.clone()),
+            _ => None,
+        }
+    }
+
+    /// Applies `f` to a record of any session and writes it back.
+    fn update_record(&mut self, id: u64, f: impl FnOnce(&mut Record)) -> Option<Record> {
+        let record = match self.records.iter_mut().rev().find(|r| r.id == id) {
+            Some(record) => {
+                f(record);
+                record.clone()
+            }
+            None => match self.store.record(id) {
+                Ok(Some(mut record)) => {
+                    f(&mut record);
+                    record
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    self.report_store_error(e);
+                    return None;
+                }
+            },
+        };
+        self.journal(&record);
+        let result = self.store.update(&record);
+        self.stored(result);
+        Some(record)
+    }
+
+    /// Hands a record that has its reading, or failed to, to the sinks.
+    fn record_completed(&mut self, record: &Record) {
+        self.auto_export(record);
+        if let Some(mqtt) = &mut self.mqtt {
+            mqtt.publish_record(record);
+        }
+        if self.settings.webhook.enabled {
+            if let Err(e) = self.store.enqueue_delivery(record) {
+                self.report_store_error(e);
+            }
+        }
+    }
+
+    /// Announces whether a verified label matched the device.
+    fn show_verdict(&mut self, record: &Record, verdict: Verdict) {
+        let verification = &self.settings.verification;
+        let serial = match verification.device_serial {
+            verification::DeviceSerial::Hex => record.field(Field::SerialHex),
+            verification::DeviceSerial::Dec => record.field(Field::SerialDec),
+        };
+        let (title, color) = match verdict {
+            Verdict::Pass => ("✔ PASS", Color32::DARK_GREEN),
+            Verdict::Fail => ("✖ FAIL", Color32::DARK_RED),
+        };
+        if verdict == Verdict::Fail {
+            warn!(
+                "Label {:?} does not match device serial {}",
+                record.barcode, serial
+            );
+        }
+        self.banner = Some(Banner {
+            title: format!("{title} {}", record.field(Field::Barcode)),
+            detail: format!("Device serial {serial}"),
+            color,
+        });
+        verification.play(verdict);
+    }
+
+    fn auto_export(&mut self, record: &Record) {
+        let auto_export = &self.settings.auto_export;
+        if !auto_export.enabled {
+            return;
+        }
+        match auto_export.append(record, &self.settings.export) {
+            Ok(()) => self.export_error = None,
+            Err(e) => {
+                error!("Auto-export failed: {:?}", e);
+                self.export_error = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    fn journal(&mut self, record: &Record) {
+        if let Some(Err(e)) = self.journal.as_mut().map(|j| j.append(record)) {
+            self.report_store_error(e.context("Journal write failed"));
+        }
+    }
+
+    /// Reports the database write of a change already journalled.
+    fn stored(&mut self, result: anyhow::Result<()>) {
+        let ok = result.is_ok();
+        if let Err(e) = result {
+            self.report_store_error(e);
+        }
+        if let Some(Err(e)) = self.journal.as_mut().map(|j| j.stored(ok)) {
+            self.report_store_error(e.context("Journal checkpoint failed"));
+        }
+    }
+
+    /// Starts or stops the HTTP API to match the settings and answers its
+    /// queued requests.
+    fn update_api(&mut self, ctx: &egui::Context) {
+        if !self.settings.api.enabled {
+            self.api = None;
+            return;
+        }
+        if self.api.is_none() && self.api_error.is_none() {
+            match api::Server::start(&self.settings.api.address, Some(Arc::new(ctx.clone()))) {
+                Ok(server) => self.api = Some(server),
+                Err(e) => {
+                    error!("Failed to start HTTP API: {:?}", e);
+                    self.api_error = Some(format!("{:#}", e));
+                }
+            }
+        }
+        while let Some((request, responder)) = self.api.as_ref().and_then(|api| api.try_next()) {
+            match request {
+                api::Request::Records => responder.respond(200, &self.records),
+                api::Request::Status => {
+                    let (status, port) = self.connection_status.parts();
+                    responder.respond(
+                        200,
+                        serde_json::json!({
+                            "connection": status,
+                            "port": port,
+                            "session_id": self.session_id,
+                            "station": self.settings.station_id,
+                            "operator": self.operator,
+                            "records": self.records.len(),
+                            "mismatches": self.mismatches(),
+                        }),
+                    );
+                }
+                api::Request::Barcode(barcode) => match self.scan(barcode, Local::now()) {
+                    Ok(()) => responder.respond(201, self.records.last()),
+                    Err(reason) => responder.respond(422, serde_json::json!({ "error": reason })),
+                },
+                api::Request::Download(file) => {
+                    let dir = self.settings.api.export_dir.clone();
+                    let records = self.records.clone();
+                    let options = self.settings.export.clone();
+                    // Failures go back to the caller, not to a dialog on the station.
+                    std::thread::spawn(move || {
+                        let path = dir.join(file);
+                        let written = std::fs::create_dir_all(&dir)
+                            .map_err(|e| anyhow::anyhow!("Failed to create {dir:?}: {e}"))
+                            .and_then(|_| export::write(&path, &records, &options));
+                        match written {
+                            Ok(()) => responder.respond(201, serde_json::json!({ "path": path })),
+                            Err(e) => {
+                                let error = format!("Download failed: {e:#}");
+                                responder.respond(500, serde_json::json!({ "error": error }));
+                            }
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    fn update_mqtt(&mut self) {
+        if !self.settings.mqtt.enabled {
+            self.mqtt = None;
+            return;
+        }
+        if self.mqtt.is_none() {
+            let mut mqtt = Mqtt::start(&self.settings.mqtt, &self.settings.station_id);
+            let (status, port) = self.connection_status.parts();
+            mqtt.publish_status(status, port);
+            // The scanner may have failed before there was a client to tell.
+            if !self.is_scanner_alive && !self.keyboard {
+                mqtt.publish_scanner_failed();
+            }
+            self.mqtt = Some(mqtt);
+        }
+    }
+
+    fn set_connection_status(&mut self, status: ConnectionStatus) {
+        if let Some(mqtt) = &mut self.mqtt {
+            if status.parts() != self.connection_status.parts() {
+                let (status, port) = status.parts();
+                mqtt.publish_status(status, port);
+            }
+        }
+        self.connection_status = status;
+    }
+
+    fn update_webhook(&mut self, ctx: &egui::Context) {
+        if self.webhook.is_none() && !self.settings.webhook.enabled {
+            return;
+        }
+        let webhook = self
+            .webhook
+            .get_or_insert_with(|| Webhook::start(Some(Arc::new(ctx.clone()))));
+        if let Err(e) = webhook.update(&self.store, &self.settings.webhook) {
+            self.report_store_error(e);
+        }
+    }
+
+    fn flush_receive_channel(&mut self, _ctx: &egui::Context) {
+        while let Some(event) = self.events.try_next() {
+            debug!("Received event: {:?}", event);
+            match event {
+                Reply::Read(id, info) => {
+                    let port = self.connected_port();
+                    let verification = self.settings.verification.clone();
+                    let before = self.find_record(id);
+                    if let Some(record) = self.update_record(id, |record| {
+                        record.apply_read(&info, port);
+                        record.verification = verification.check(record);
+                    }) {
+                        // A re-read replacing a serial the record already had.
+                        if let Some(before) = before
+                            .filter(|b| b.serial_hex.is_some() && b.serial_hex != record.serial_hex)
+                        {
+                            self.audit_changes(&before, &record);
+                        }
+                        if let Some(verdict) = record.verification {
+                            self.show_verdict(&record, verdict);
+                        }
+                        let record = self.check_serial_duplicate(record);
+                        self.record_completed(&record);
+                    }
+                }
+                Reply::Connected(d) => {
+                    self.set_connection_status(ConnectionStatus::Connected(d));
+                }
+                Reply::Connecting => {
+                    self.set_connection_status(ConnectionStatus::Connecting);
+                }
+                Reply::Disconnected => {
+                    self.set_connection_status(ConnectionStatus::Disconnected);
+                }
+                Reply::ReadError(id, status, s) => {
+                    debug!("Read error ({:?}): {}", status, s);
+                    let port = self.connected_port();
+                    if let Some(record) =
+                        self.update_record(id, |record| record.apply_error(status, &s, port))
+                    {
+                        self.record_completed(&record);
+                    }
+                }
+                Reply::DownloadError(e) => {
+                    debug!("Download error: {}", e);
+                    self.show_download_error_dialog(&e);
+                }
+                Reply::BarcodeOutput(s, scanned_at) => {
+                    if !s.trim().is_empty() {
+                        let _ = self.scan(s, scanned_at);
+                    }
+                }
+                Reply::ScannerStartFail => {
+                    self.is_scanner_alive = false;
+                    if let Some(mqtt) = &mut self.mqtt {
+                        mqtt.publish_scanner_failed();
+                    }
+                }
+                Reply::Ports(ports) => {
+                    self.ports = Some(ports);
+                }
+            }
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, &AppStorage::from(self));
+    }
+
+    fn auto_save_interval(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Keeps the connection checks in update_non_ui running while idle.
+        ctx.request_repaint_after(Duration::from_millis(200));
+        self.update_non_ui();
+        self.flush_receive_channel(ctx);
+        self.update_api(ctx);
+        self.update_webhook(ctx);
+        self.update_mqtt();
+        self.handle_shortcuts(ctx);
+        egui::TopBottomPanel::top("top_panel")
+            .exact_height(50.0)
+            .show(ctx, |ui| {
+                ui.horizontal_centered(|ui| {
+                    let status = ui.add(
+                        Label::new(
+                            RichText::new(match &self.connection_status {
+                                ConnectionStatus::Connected(port) => format!("Connected ({port})"),
+                                ConnectionStatus::Connecting => {
+                                    "Attempting Connection...".to_string()
+                                }
+                                ConnectionStatus::Disconnected => "Disconnected".to_string(),
+                            })
+                            .heading(),
+                        )
+                        .sense(Sense::click()),
+                    );
+                    if status.on_hover_text("Choose device").clicked() {
+                        self.show_devices = !self.show_devices;
+                        if self.show_devices {
+                            self.refresh_ports();
+                        }
+                    }
+                    if let Some(port) = &self.settings.device.pinned_port {
+                        ui.label(format!("📌 {port}"));
+                    }
+                    ui.label(format!("Session {}", self.session_id));
+                    if self.settings.verification.enabled {
+                        let verified = self
+                            .records
+                            .iter()
+                            .filter(|r| r.verification.is_some())
+                            .count();
+                        let mismatches = self.mismatches();
+                        ui.label(format!("✔ {}", verified - mismatches))
+                            .on_hover_text("Labels matching the device");
+                        ui.colored_label(
+                            if mismatches > 0 {
+                                Color32::RED
+                            } else {
+                                ui.visuals().text_color()
+                            },
+                            format!("✖ {mismatches}"),
+                        )
+                        .on_hover_text("Labels not matching the device");
+                    }
+                    if let Some(webhook) = self
+                        .webhook
+                        .as_mut()
+                        .filter(|_| self.settings.webhook.enabled)
+                    {
+                        let (pending, failed) = webhook.counts;
+                        ui.label(format!("📤 {pending}"))
+                            .on_hover_text("Records waiting to be posted to the webhook");
+                        if failed > 0
+                            && ui
+                                .add(Button::new(
+                                    RichText::new(format!("⚠ {failed} failed")).color(Color32::RED),
+                                ))
+                                .on_hover_text("Retry failed deliveries")
+                                .clicked()
+                        {
+                            if let Err(e) = webhook.retry_failed(&self.store) {
+                                error!("Failed to retry deliveries: {:?}", e);
+                            }
+                        }
+                    }
+                    let operator = match self.operator.as_str() {
+                        "" => "Log in".to_string(),
+                        name => format!("👤 {name}"),
+                    };
+                    if ui.button(operator).clicked() {
+                        self.operator_draft = Some(self.operator.clone());
+                    }
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        let clear_button =
+                            Button::new(RichText::new("Clear").heading()).fill(Color32::RED);
+                        if ui.add(clear_button).clicked()
+                            && ask_confirmation(
+                                "Start a new session? The current records stay in history.",
+                            )
+                        {
+                            self.clear();
+                        };
+                        if ui
+                            .add_enabled(
+                                self.undo.can_redo(),
+                                Button::new(RichText::new("↷").heading()),
+                            )
+                            .on_hover_text("Redo (Ctrl+Y)")
+                            .clicked()
+                        {
+                            self.redo();
+                        }
+                        if ui
+                            .add_enabled(
+                                self.undo.can_undo(),
+                                Button::new(RichText::new("↶").heading()),
+                            )
+                            .on_hover_text("Undo (Ctrl+Z)")
+                            .clicked()
+                        {
+                            self.undo();
+                        }
+                        let download_bytton =
+                            Button::new(RichText::new("Download").heading()).rounding(5.0);
+                        if ui.add(download_bytton).clicked() {
+                            self.start_download(self.records.clone());
+                        };
+                        if ui
+                            .add(
+                                Button::new(RichText::new("🕒").heading())
+                                    .selected(self.sessions.is_some()),
+                            )
+                            .on_hover_text("History")
+                            .clicked()
+                        {
+                            match self.sessions {
+                                Some(_) => self.sessions = None,
+                                None => self.refresh_sessions(),
+                            }
+                        }
+                        if ui
+                            .add(
+                                Button::new(RichText::new("⚙").heading())
+                                    .selected(self.settings_draft.is_some()),
+                            )
+                            .clicked()
+                        {
+                            self.settings_draft = match self.settings_draft {
+                                Some(_) => None,
+                                None => Some(self.settings.clone()),
+                            };
+                        }
+                        if ui
+                            .add(Button::new(RichText::new("⌨").heading()).selected(self.keyboard))
+                            .clicked()
+                        {
+                            self.keyboard = !self.keyboard;
+                            if self.keyboard {
+                                self.send(Command::StopScanner);
+                                self.is_scanner_alive = false;
+                            } else {
+                                self.send(Command::StartScanner);
+                                self.is_scanner_alive = true;
+                            }
+                        }
+                    });
+                });
+            });
+        if let Some(banner) = &self.banner {
+            let mut dismiss = false;
+            egui::TopBottomPanel::top("banner_panel")
+                .frame(Frame::side_top_panel(&ctx.style()).fill(banner.color))
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(
+                            RichText::new(&banner.title)
+                                .size(32.0)
+                                .strong()
+                                .color(Color32::WHITE),
+                        );
+                        ui.label(
+                            RichText::new(&banner.detail)
+                                .heading()
+                                .color(Color32::WHITE),
+                        );
+                        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                            dismiss = ui.button("✖").clicked();
+                        });
+                    });
+                });
+            if dismiss {
+                self.banner = None;
+            }
+        }
+        egui::TopBottomPanel::bottom("bottom_panel")
+            .exact_height(40.0)
+            .show(ctx, |ui| {
+                ui.horizontal_centered(|ui| {
+                    if let Some(e) = &self.store_error {
+                        ui.colored_label(Color32::RED, format!("Database error: {e}"));
+                        if ui.small_button("✖").clicked() {
+                            self.store_error = None;
+                        }
+                        ui.separator();
+                    }
+                    if let Some(e) = &self.api_error {
+                        ui.colored_label(Color32::RED, format!("HTTP API failed: {e}"));
+                        if ui.small_button("✖").clicked() {
+                            self.settings.api.enabled = false;
+                            self.api_error = None;
+                        }
+                        ui.separator();
+                    }
+                    if let Some(e) = &self.export_error {
+                        ui.colored_label(Color32::RED, format!("Auto-export failed: {e}"));
+                        if ui.small_button("✖").clicked() {
+                            self.export_error = None;
+                        }
+                        ui.separator();
+                    }
+                    if !self.keyboard {
+                        if self.is_scanner_alive {
+                            ui.label("Scanning barcodes...");
+                        } else {
+                            ui.add(Label::new(
+                                RichText::new("Barcode scanner task failed to start")
+                                    .color(Color32::RED),
+                            ));
+                        }
+                        return;
+                    }
+                    let input_box = ui.add(
+                        egui::TextEdit::singleline(&mut self.text)
+                            .id(Id::new(SCAN_BOX))
+                            .desired_width(ui.available_width()),
+                    );
+                    // input_box.request_focus();
+                    if input_box.ctx.input(|i| i.key_pressed(egui::Key::Enter))
+                        && !self.text.trim().is_empty()
+                    {
+                        let _ = self.scan(self.text.clone(), Local::now());
+                        self.text.clear();
+                        input_box.request_focus();
+                    }
+                });
+            });
+        self.show_settings_window(ctx);
+        self.show_devices_window(ctx);
+        self.show_operator_window(ctx);
+        self.show_history_window(ctx);
+        self.show_duplicate_window(ctx);
+        self.show_edit_window(ctx);
+        self.show_audit_window(ctx);
+        let mut retry_failed = false;
+        let mut reread = None;
+        let mut edit = None;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Show");
+                let count = |filter: StatusFilter| {
+                    self.records.iter().filter(|r| filter.matches(r)).count()
+                };
+                let name = |filter: StatusFilter| format!("{} ({})", filter.name(), count(filter));
+                ComboBox::from_id_source("status_filter")
+                    .selected_text(name(self.status_filter))
+                    .show_ui(ui, |ui| {
+                        for filter in StatusFilter::all() {
+                            ui.selectable_value(&mut self.status_filter, filter, name(filter));
+                        }
+                    });
+                let failed = count(StatusFilter::Failed);
+                if failed > 0
+                    && ui
+                        .button(format!("⟳ Retry {failed} failed"))
+                        .on_hover_text("Read the device again for every failed record")
+                        .clicked()
+                {
+                    retry_failed = true;
+                }
+                if !self.rereads.is_empty() {
+                    ui.label(format!("⏳ {} waiting for the device", self.rereads.len()));
+                }
+            });
+            let rows: Vec<usize> = (0..self.records.len())
+                .filter(|&i| self.status_filter.matches(&self.records[i]))
+                .collect();
+            ScrollArea::horizontal().auto_shrink(false).show(ui, |ui| {
+                let width = ui.available_width();
+                let height = ui.text_style_height(&TextStyle::Body);
+                TableBuilder::new(ui)
+                    .stick_to_bottom(true)
+                    .striped(true)
+                    .resizable(true)
+                    .cell_layout(Layout::left_to_right(Align::Center))
+                    .column(Column::auto())
+                    .columns(
+                        Column::initial(width / 6.0)
+                            .clip(true)
+                            .at_least(width / 12.0)
+                            .at_most(width / 3.0),
+                        Field::ALL.len() - 1,
+                    )
+                    .column(
+                        Column::remainder()
+                            .clip(true)
+                            .at_least(width / 12.0)
+                            .at_most(width / 3.0),
+                    )
+                    .header(1.2 * height, |mut header| {
+                        header.col(|_| {});
+                        Field::ALL.into_iter().for_each(|field| {
+                            header.col(|ui| {
+                                ui.add(
+                                    Label::new(RichText::new(field.header()).strong()).wrap(false),
+                                );
+                            });
+                        });
+                    })
+                    .body(|body| {
+                        body.rows(height, rows.len(), |i, mut row| {
+                            let record = &self.records[rows[i]];
+                            let color = row_color(record);
+                            row.col(|ui| {
+                                let queued = self.rereads.contains(&record.id);
+                                if ui
+                                    .add_enabled(!queued, Button::new("⟳").small())
+                                    .on_hover_text("Re-read")
+                                    .clicked()
+                                {
+                                    reread = Some(record.id);
+                                }
+                                if ui
+                                    .add(Button::new("✏").small())
+                                    .on_hover_text("Edit, annotate or delete")
+                                    .clicked()
+                                {
+                                    edit = Some(record.id);
+                                }
+                            });
+                            for cell in Field::ALL.map(|f| record.field(f)) {
+                                row.col(|ui| {
+                                    let text = if cell.is_empty() { "-" } else { &cell };
+                                    let label = match color {
+                                        Some(color) => RichText::new(text).color(color),
+                                        None => RichText::new(text),
+                                    };
+                                    let label = ui.add(Label::new(label).wrap(false));
+                                    if let Some(duplicate) = record.duplicate {
+                                        label.on_hover_text(format!(
+                                            "Duplicate of record {}",
+                                            duplicate.of
+                                        ));
+                                    }
+                                });
+                            }
+                        });
+                    });
+            })
+        });
+        if retry_failed {
+            self.retry_failed();
+        }
+        if let Some(id) = reread {
+            self.reread(id);
+        }
+        if let Some(id) = edit {
+            self.open_row_edit(id);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+use anyhow::{Context, Result};
+#[cfg(feature = "gui")]
+use egui::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    export::{self, CsvOptions},
+    record::Record,
+};
+
+const DATA_DIR: &str = "sn-tracer";
+const EXPORT_DIR: &str = "exports";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Daily,
+    PerSession,
+}
+
+/// Appends every completed record to a file in `dir` as soon as it is read.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct AutoExport {
+    pub enabled: bool,
+    pub format: Format,
+    pub rotation: Rotation,
+    pub dir: PathBuf,
+}
+
+impl Default for AutoExport {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: Format::Csv,
+            rotation: Rotation::Daily,
+            dir: dirs::data_dir()
+                .map(|d| d.join(DATA_DIR).join(EXPORT_DIR))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+        }
+    }
+}
+
+impl AutoExport {
+    /// File the record belongs in under the current rotation.
+    pub fn path(&self, record: &Record) -> PathBuf {
+        let name = match self.rotation {
+            Rotation::Daily => format!("records-{}", record.scanned_at.format("%Y-%m-%d")),
+            Rotation::PerSession => format!("session-{}", record.session_id),
+        };
+        self.dir.join(name).with_extension(self.format.extension())
+    }
+
+    /// CSV files get the header and byte order mark of `csv` when created.
+    pub fn append(&self, record: &Record, csv: &CsvOptions) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {:?}", self.dir))?;
+        let path = self.path(record);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        let new_file = file.metadata()?.len() == 0;
+        let data = match self.format {
+            Format::Csv => export::encode(
+                &export::csv_rows(std::slice::from_ref(record), csv, new_file)?,
+                csv.encoding,
+                new_file,
+            ),
+            Format::JsonLines => {
+                let mut line = serde_json::to_string(record)?;
+                line.push('\n');
+                line.into_bytes()
+            }
+        };
+        file.write_all(&data)
+            .with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(())
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "Append each record as it is read");
+        Grid::new("auto_export").num_columns(2).show(ui, |ui| {
+            ui.label("Format");
+            ComboBox::from_id_source("auto_export_format")
+                .selected_text(format_name(self.format))
+                .show_ui(ui, |ui| {
+                    for f in [Format::Csv, Format::JsonLines] {
+                        ui.selectable_value(&mut self.format, f, format_name(f));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("New file");
+            ComboBox::from_id_source("auto_export_rotation")
+                .selected_text(rotation_name(self.rotation))
+                .show_ui(ui, |ui| {
+                    for r in [Rotation::Daily, Rotation::PerSession] {
+                        ui.selectable_value(&mut self.rotation, r, rotation_name(r));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Folder");
+            ui.horizontal(|ui| {
+                let mut dir = self.dir.display().to_string();
+                if ui.text_edit_singleline(&mut dir).changed() {
+                    self.dir = dir.into();
+                }
+                if ui.button("Browse").clicked() {
+                    if let Some(dir) = rfd::FileDialog::new()
+                        .set_directory(&self.dir)
+                        .pick_folder()
+                    {
+                        self.dir = dir;
+                    }
+                }
+            });
+            ui.end_row();
+        });
+        ui.label("CSV files use the delimiter, encoding and columns above.");
+    }
+}
+
+#[cfg(feature = "gui")]
+fn format_name(format: Format) -> &'static str {
+    match format {
+        Format::Csv => "CSV",
+        Format::JsonLines => "JSON lines",
+    }
+}
+
+#[cfg(feature = "gui")]
+fn rotation_name(rotation: Rotation) -> &'static str {
+    match rotation {
+        Rotation::Daily => "Every day",
+        Rotation::PerSession => "Every session",
+    }
+}
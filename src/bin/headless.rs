@@ -0,0 +1,243 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use clap::{Parser, ValueEnum};
+use log::*;
+use sn_tracer_egui::{
+    export::{self, Encoding},
+    record::Record,
+    service::{self, Command, Reply},
+    settings::Settings,
+    transport,
+};
+
+const TICK: Duration = Duration::from_millis(200);
+
+/// Traces barcodes against the device without the GUI.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Barcodes, one per line. Stdin when neither this nor --scanner is given.
+    #[arg(short, long, conflicts_with = "scanner")]
+    input: Option<PathBuf>,
+    /// Take barcodes from the scanner helper.
+    #[arg(long)]
+    scanner: bool,
+    /// Records file. Stdout when unset.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+    #[arg(long, default_value = "")]
+    operator: String,
+    /// Seconds to wait for the device before a read fails as not connected.
+    #[arg(long, default_value_t = 10)]
+    connect_timeout: u64,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Csv,
+    Jsonl,
+}
+
+enum Event {
+    Reply(Reply),
+    Barcode(String, DateTime<Local>),
+    EndOfInput,
+}
+
+fn read_barcodes(reader: impl BufRead, events: mpsc::Sender<Event>) {
+    for line in reader.lines() {
+        match line {
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) => {
+                let _ = events.send(Event::Barcode(line.trim().to_string(), Local::now()));
+            }
+            Err(e) => {
+                error!("Failed to read barcodes: {:?}", e);
+                break;
+            }
+        }
+    }
+    let _ = events.send(Event::EndOfInput);
+}
+
+struct Output {
+    writer: Box<dyn Write>,
+    settings: Settings,
+    format: OutputFormat,
+    encoding: Encoding,
+    start_of_file: bool,
+}
+
+impl Output {
+    fn write(&mut self, record: &Record) -> Result<()> {
+        let data = match self.format {
+            OutputFormat::Csv => export::encode(
+                &export::csv_rows(
+                    std::slice::from_ref(record),
+                    &self.settings.export,
+                    self.start_of_file,
+                )?,
+                self.encoding,
+                self.start_of_file,
+            ),
+            OutputFormat::Jsonl => {
+                let mut line = serde_json::to_string(record)?;
+                line.push('\n');
+                line.into_bytes()
+            }
+        };
+        self.start_of_file = false;
+        self.writer.write_all(&data)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_default_env().init();
+    let args = Args::parse();
+    let settings = Settings::load();
+
+    let (service, mut replies) =
+        service::spawn_thread(transport::from_settings(&settings.device), None);
+
+    let (event_send, events) = mpsc::channel();
+    std::thread::spawn({
+        let event_send = event_send.clone();
+        move || {
+            while let Some(reply) = replies.blocking_next() {
+                if event_send.send(Event::Reply(reply)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    let send = |command| service.send(command);
+    if !args.scanner {
+        send(Command::StopScanner)?;
+        match &args.input {
+            Some(path) => {
+                let file =
+                    File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+                std::thread::spawn(move || read_barcodes(BufReader::new(file), event_send));
+            }
+            None => {
+                std::thread::spawn(move || read_barcodes(std::io::stdin().lock(), event_send));
+            }
+        }
+    }
+
+    let (writer, encoding): (Box<dyn Write>, _) = match &args.output {
+        Some(path) => (
+            Box::new(File::create(path).with_context(|| format!("Failed to create {:?}", path))?),
+            settings.export.encoding,
+        ),
+        None => (Box::new(std::io::stdout().lock()), Encoding::Utf8),
+    };
+    let mut output = Output {
+        writer,
+        settings,
+        format: args.format,
+        encoding,
+        start_of_file: true,
+    };
+
+    let connect_timeout = Duration::from_secs(args.connect_timeout);
+    let mut queue = std::collections::VecDeque::new();
+    let mut reading: Option<Record> = None;
+    let mut port: Option<String> = None;
+    let mut connecting = true;
+    let mut disconnected_since = Instant::now();
+    let mut end_of_input = false;
+    let mut next_id = 1;
+    let mut failed = 0;
+
+    send(Command::Connect)?;
+    loop {
+        match events.recv_timeout(TICK) {
+            Ok(Event::Barcode(barcode, scanned_at))
+            | Ok(Event::Reply(Reply::BarcodeOutput(barcode, scanned_at)))
+                if !barcode.trim().is_empty() =>
+            {
+                queue.push_back(Record::new(
+                    next_id,
+                    0,
+                    barcode,
+                    scanned_at,
+                    output.settings.station_id.clone(),
+                    args.operator.clone(),
+                ));
+                next_id += 1;
+            }
+            Ok(Event::Barcode(..)) => {}
+            Ok(Event::EndOfInput) => end_of_input = true,
+            Ok(Event::Reply(Reply::Connected(name))) => {
+                info!("Connected to {}", name);
+                port = Some(name);
+                connecting = false;
+            }
+            Ok(Event::Reply(Reply::Disconnected)) => {
+                if port.take().is_some() {
+                    disconnected_since = Instant::now();
+                }
+                connecting = false;
+            }
+            Ok(Event::Reply(Reply::Read(id, reply))) => {
+                if let Some(mut record) = reading.take_if(|r| r.id == id) {
+                    record.apply_read(&reply, port.clone());
+                    output.write(&record)?;
+                }
+            }
+            Ok(Event::Reply(Reply::ReadError(id, status, e))) => {
+                if let Some(mut record) = reading.take_if(|r| r.id == id) {
+                    record.apply_error(status, &e, port.clone());
+                    failed += 1;
+                    output.write(&record)?;
+                }
+            }
+            Ok(Event::Reply(Reply::ScannerStartFail)) if args.scanner => {
+                anyhow::bail!("Barcode scanner failed to start");
+            }
+            Ok(Event::Reply(reply)) => debug!("Ignoring {:?}", reply),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("Service stopped"),
+        }
+
+        if reading.is_some() {
+            continue;
+        }
+        if queue.is_empty() {
+            if end_of_input {
+                break;
+            }
+            continue;
+        }
+        if port.is_none() {
+            if !connecting {
+                send(Command::Connect)?;
+                connecting = true;
+            }
+            if disconnected_since.elapsed() < connect_timeout {
+                continue;
+            }
+        }
+        let record = queue.pop_front().unwrap();
+        send(Command::Read(record.id))?;
+        reading = Some(record);
+    }
+
+    info!("Traced {} records, {} failed", next_id - 1, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
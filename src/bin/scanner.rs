@@ -4,6 +4,8 @@
 )]
 use clap::Parser;
 use log::*;
+use rdev::{EventType, Key};
+use sn_tracer_egui::gs1::GS;
 use std::time::Instant;
 use sysinfo::{System, SystemExt};
 
@@ -32,13 +34,32 @@ fn main() {
         });
     }
     let mut events: Vec<(String, Instant)> = Vec::new();
-    if let Err(e) = rdev::listen(move |event| match (event.name, events.last()) {
+    let mut ctrl = false;
+    if let Err(e) = rdev::listen(move |event| {
+        match event.event_type {
+            EventType::KeyPress(Key::ControlLeft | Key::ControlRight) => ctrl = true,
+            EventType::KeyRelease(Key::ControlLeft | Key::ControlRight) => ctrl = false,
+            _ => {}
+        }
+        // Scanners type FNC1 as Ctrl+], which has no name on some platforms.
+        let name = match event.event_type {
+            EventType::KeyPress(Key::RightBracket) if ctrl => Some(GS.to_string()),
+            _ => event.name,
+        };
+        handle(name, &mut events)
+    }) {
+        error!("Error: {:?}", e);
+    }
+}
+
+fn handle(name: Option<String>, events: &mut Vec<(String, Instant)>) {
+    match (name, events.last()) {
         (None, _) => {}
         (Some(s), None) => {
             events.push((s, Instant::now()));
         }
         (Some(s), Some((_, last_t)))
-            if last_t.elapsed().as_millis() < DELAY_MILLIS && s == "\r".to_string() =>
+            if last_t.elapsed().as_millis() < DELAY_MILLIS && s == "\r" =>
         {
             let res = events.iter().map(|(s, _)| s).cloned().collect::<String>();
             trace!("{}", last_t.elapsed().as_millis());
@@ -54,7 +75,5 @@ fn main() {
             events.clear();
             events.push((s, Instant::now()));
         }
-    }) {
-        error!("Error: {:?}", e);
     }
 }
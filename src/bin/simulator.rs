@@ -0,0 +1,146 @@
+//! Fake device on a pseudo-terminal. Point the app at it with
+//! `SN_TRACER_PORT=<printed path>`.
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Create a symlink to the pty at this path
+    #[arg(short, long, value_name = "PATH")]
+    link: Option<PathBuf>,
+
+    /// Serial number (hex) to report, may be repeated and is cycled through
+    #[arg(short, long = "serial", value_name = "HEX", value_parser = parse_hex)]
+    serials: Vec<u64>,
+
+    /// First serial number (hex) when no --serial is given, incremented per read
+    #[arg(long, value_name = "HEX", default_value = "1000", value_parser = parse_hex)]
+    first_serial: u64,
+
+    /// Manufacture date to report
+    #[arg(short, long, default_value = "2023-12-01")]
+    date: String,
+
+    /// Delay before every reply
+    #[arg(long, value_name = "MILLIS", default_value_t = 0)]
+    latency: u64,
+
+    /// Probability of replying with a garbage line
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    garbage: f64,
+
+    /// Probability of not replying at all
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    dropout: f64,
+}
+
+fn parse_hex(s: &str) -> Result<u64, std::num::ParseIntError> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+}
+
+#[cfg(unix)]
+mod pty {
+    use super::Args;
+    use anyhow::{Context, Result};
+    use log::*;
+    use rand::{distributions::Alphanumeric, Rng};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        time::{sleep, Duration},
+    };
+    use tokio_serial::{SerialPort, SerialStream};
+
+    struct Device {
+        args: Args,
+        reads: usize,
+    }
+
+    impl Device {
+        fn next_serial(&mut self) -> u64 {
+            let serial = match self.args.serials.as_slice() {
+                [] => self.args.first_serial + self.reads as u64,
+                serials => serials[self.reads % serials.len()],
+            };
+            self.reads += 1;
+            serial
+        }
+
+        fn reply(&mut self, request: &str) -> Option<String> {
+            let mut rng = rand::thread_rng();
+            if rng.gen_bool(self.args.dropout) {
+                info!("Dropping {:?}", request);
+                return None;
+            }
+            if rng.gen_bool(self.args.garbage) {
+                let len = rng.gen_range(1..32);
+                return Some(
+                    (&mut rng)
+                        .sample_iter(&Alphanumeric)
+                        .take(len)
+                        .map(char::from)
+                        .collect(),
+                );
+            }
+            match request {
+                "connect" => Some("connected".into()),
+                "read" => {
+                    let serial = self.next_serial();
+                    Some(format!("{:X},{},{}", serial, serial, self.args.date))
+                }
+                _ => {
+                    warn!("Unknown request: {:?}", request);
+                    None
+                }
+            }
+        }
+    }
+
+    #[tokio::main]
+    pub async fn run(args: Args) -> Result<()> {
+        for p in [args.garbage, args.dropout] {
+            anyhow::ensure!((0.0..=1.0).contains(&p), "Probability out of range: {p}");
+        }
+        let (master, mut slave) = SerialStream::pair()?;
+        // Keep the slave end open so the master survives the app disconnecting,
+        // but let the app open it too.
+        slave.set_exclusive(false)?;
+        let slave_name = slave.name().context("Pseudo-terminal has no name")?;
+        let path = match &args.link {
+            Some(link) => {
+                let _ = std::fs::remove_file(link);
+                std::os::unix::fs::symlink(&slave_name, link)?;
+                link.display().to_string()
+            }
+            None => slave_name,
+        };
+        println!("{path}");
+
+        let latency = Duration::from_millis(args.latency);
+        let mut device = Device { args, reads: 0 };
+        let (reader, mut writer) = tokio::io::split(master);
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let request = line.trim();
+            debug!("Request: {:?}", request);
+            if let Some(reply) = device.reply(request) {
+                sleep(latency).await;
+                debug!("Reply: {:?}", reply);
+                writer.write_all(format!("{reply}\n").as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_default_env().init();
+    pty::run(Args::parse())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    let _ = Args::parse();
+    eprintln!("The simulator needs a Unix pseudo-terminal");
+    std::process::exit(1);
+}
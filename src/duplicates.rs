@@ -0,0 +1,207 @@
+use anyhow::Result;
+#[cfg(feature = "gui")]
+use egui::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    record::{Duplicate, DuplicateKind, Record},
+    store::Store,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DuplicateSettings {
+    pub enabled: bool,
+    /// Checks records of earlier sessions too, not just the open one.
+    pub history: bool,
+    pub barcodes: bool,
+    pub serials: bool,
+}
+
+impl Default for DuplicateSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            history: true,
+            barcodes: true,
+            serials: true,
+        }
+    }
+}
+
+impl DuplicateSettings {
+    /// The earlier record `record` repeats, by barcode before it is read and
+    /// by device serial after.
+    pub fn find(
+        &self,
+        record: &Record,
+        session: &[Record],
+        store: &Store,
+    ) -> Result<Option<Duplicate>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let in_session = |same: &dyn Fn(&Record) -> bool| {
+            session
+                .iter()
+                .find(|r| r.id != record.id && same(r))
+                .cloned()
+        };
+        let (kind, earlier) = match &record.serial_hex {
+            None if self.barcodes => {
+                let earlier = match in_session(&|r| r.barcode == record.barcode) {
+                    None if self.history => store.record_by_barcode(&record.barcode, record.id)?,
+                    earlier => earlier,
+                };
+                (DuplicateKind::Barcode, earlier)
+            }
+            Some(serial) if self.serials => {
+                let earlier = match in_session(&|r| r.serial_hex.as_ref() == Some(serial)) {
+                    None if self.history => store.record_by_serial(serial, record.id)?,
+                    earlier => earlier,
+                };
+                (DuplicateKind::Serial, earlier)
+            }
+            _ => return Ok(None),
+        };
+        Ok(earlier.map(|earlier| Duplicate {
+            of: earlier.id,
+            session_id: earlier.session_id,
+            kind,
+        }))
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "Ask what to do with duplicates");
+        ui.add_enabled_ui(self.enabled, |ui| {
+            ui.checkbox(&mut self.barcodes, "Barcodes scanned twice");
+            ui.checkbox(&mut self.serials, "Device serials read for two barcodes");
+            ui.checkbox(&mut self.history, "Include earlier sessions");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use super::*;
+    use crate::record::DeviceInfo;
+
+    struct Fixture {
+        store: Store,
+        session: Vec<Record>,
+        current: u64,
+    }
+
+    fn read(record: &mut Record, serial: u64) {
+        let info = DeviceInfo::parse(&format!("{serial:X},{serial},2023-01-01")).unwrap();
+        record.apply_read(&info, None);
+    }
+
+    fn stored(store: &Store, session_id: u64, barcode: &str, serial: u64) -> Record {
+        let mut record = Record::new(
+            0,
+            session_id,
+            barcode.into(),
+            Local::now(),
+            "".into(),
+            "".into(),
+        );
+        read(&mut record, serial);
+        store.insert(&mut record).unwrap();
+        record
+    }
+
+    /// An earlier session with "OLD"/0xA, and the open one with "NEW"/0xB.
+    fn fixture() -> Fixture {
+        let store = Store::open_in_memory().unwrap();
+        let earlier = store.new_session("", "").unwrap();
+        stored(&store, earlier, "OLD", 10);
+        let current = store.new_session("", "").unwrap();
+        let session = vec![stored(&store, current, "NEW", 11)];
+        Fixture {
+            store,
+            session,
+            current,
+        }
+    }
+
+    fn find(settings: &DuplicateSettings, fixture: &Fixture, record: &Record) -> Option<Duplicate> {
+        settings
+            .find(record, &fixture.session, &fixture.store)
+            .unwrap()
+    }
+
+    fn scan(fixture: &Fixture, barcode: &str) -> Record {
+        Record::new(
+            0,
+            fixture.current,
+            barcode.into(),
+            Local::now(),
+            "".into(),
+            "".into(),
+        )
+    }
+
+    #[test]
+    fn finds_barcodes_in_session_and_history() {
+        let fixture = fixture();
+        let settings = DuplicateSettings::default();
+        let new = find(&settings, &fixture, &scan(&fixture, "NEW")).unwrap();
+        assert_eq!(
+            (new.of, new.session_id, new.kind),
+            (2, fixture.current, DuplicateKind::Barcode)
+        );
+        let old = find(&settings, &fixture, &scan(&fixture, "OLD")).unwrap();
+        assert_eq!((old.of, old.session_id), (1, fixture.current - 1));
+        assert_eq!(find(&settings, &fixture, &scan(&fixture, "OTHER")), None);
+
+        let session_only = DuplicateSettings {
+            history: false,
+            ..Default::default()
+        };
+        assert_eq!(find(&session_only, &fixture, &scan(&fixture, "OLD")), None);
+        assert!(find(&session_only, &fixture, &scan(&fixture, "NEW")).is_some());
+    }
+
+    #[test]
+    fn finds_serials_once_read() {
+        let fixture = fixture();
+        let settings = DuplicateSettings::default();
+        let mut record = scan(&fixture, "OTHER");
+        read(&mut record, 10);
+        let duplicate = find(&settings, &fixture, &record).unwrap();
+        assert_eq!((duplicate.of, duplicate.kind), (1, DuplicateKind::Serial));
+
+        let barcodes_only = DuplicateSettings {
+            serials: false,
+            ..Default::default()
+        };
+        assert_eq!(find(&barcodes_only, &fixture, &record), None);
+        let serials_only = DuplicateSettings {
+            barcodes: false,
+            ..Default::default()
+        };
+        assert_eq!(find(&serials_only, &fixture, &scan(&fixture, "NEW")), None);
+        let disabled = DuplicateSettings {
+            enabled: false,
+            ..Default::default()
+        };
+        assert_eq!(find(&disabled, &fixture, &record), None);
+    }
+
+    #[test]
+    fn ignores_the_record_itself() {
+        let fixture = fixture();
+        let settings = DuplicateSettings::default();
+        let itself = fixture.session[0].clone();
+        assert_eq!(find(&settings, &fixture, &itself), None);
+        let unread = Record {
+            serial_hex: None,
+            ..itself
+        };
+        assert_eq!(find(&settings, &fixture, &unread), None);
+    }
+}
@@ -0,0 +1,449 @@
+#[cfg(feature = "xlsx")]
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Result;
+#[cfg(feature = "xlsx")]
+use chrono::{DateTime, Local};
+#[cfg(feature = "gui")]
+use egui::*;
+#[cfg(feature = "xlsx")]
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "xlsx")]
+use crate::record::{parse_date, ReadStatus, Verdict};
+use crate::record::{Field, Record};
+
+/// Largest integer an Excel number holds exactly.
+#[cfg(feature = "xlsx")]
+const EXCEL_MAX_INT: u64 = 1 << 53;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delimiter {
+    Comma,
+    Semicolon,
+    Tab,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// What Excel needs to detect UTF-8.
+    Utf8Bom,
+    /// Excel's "Unicode Text", best paired with tabs.
+    Utf16Le,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct CsvOptions {
+    pub delimiter: Delimiter,
+    pub line_ending: LineEnding,
+    pub encoding: Encoding,
+    /// Exported columns, in order.
+    pub columns: Vec<Field>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: Delimiter::Comma,
+            line_ending: LineEnding::CrLf,
+            encoding: Encoding::Utf8Bom,
+            columns: Field::ALL.to_vec(),
+        }
+    }
+}
+
+impl Delimiter {
+    fn byte(self) -> u8 {
+        match self {
+            Delimiter::Comma => b',',
+            Delimiter::Semicolon => b';',
+            Delimiter::Tab => b'\t',
+        }
+    }
+}
+
+pub fn to_csv(records: &[Record], options: &CsvOptions) -> Result<Vec<u8>> {
+    Ok(encode(
+        &csv_rows(records, options, true)?,
+        options.encoding,
+        true,
+    ))
+}
+
+/// Delimited rows for `records`, preceded by the header row if `header`.
+pub fn csv_rows(records: &[Record], options: &CsvOptions, header: bool) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(options.delimiter.byte())
+        .terminator(match options.line_ending {
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+            LineEnding::CrLf => csv::Terminator::CRLF,
+        })
+        .from_writer(Vec::new());
+    if header {
+        writer.write_record(options.columns.iter().map(|f| f.header()))?;
+    }
+    for record in records {
+        writer.write_record(options.columns.iter().map(|&f| record.field(f)))?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Encodes `data`, with a byte order mark only at the start of a file.
+pub fn encode(data: &str, encoding: Encoding, start_of_file: bool) -> Vec<u8> {
+    match encoding {
+        Encoding::Utf8 => data.as_bytes().to_vec(),
+        Encoding::Utf8Bom if start_of_file => [&b"\xEF\xBB\xBF"[..], data.as_bytes()].concat(),
+        Encoding::Utf8Bom => data.as_bytes().to_vec(),
+        Encoding::Utf16Le => start_of_file
+            .then_some([0xFF, 0xFE])
+            .into_iter()
+            .flatten()
+            .chain(data.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect(),
+    }
+}
+
+pub fn write_csv(path: &Path, records: &[Record], options: &CsvOptions) -> Result<()> {
+    std::fs::write(path, to_csv(records, options)?)?;
+    Ok(())
+}
+
+/// Writes `.xlsx` files as a workbook and anything else as CSV.
+pub fn write(path: &Path, records: &[Record], options: &CsvOptions) -> Result<()> {
+    match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "xlsx")]
+        Some(ext) if ext.eq_ignore_ascii_case("xlsx") => {
+            write_xlsx(path, records, &options.columns)
+        }
+        #[cfg(not(feature = "xlsx"))]
+        Some(ext) if ext.eq_ignore_ascii_case("xlsx") => {
+            Err(anyhow::anyhow!("Built without Excel support"))
+        }
+        _ => write_csv(path, records, options),
+    }
+}
+
+#[cfg(feature = "xlsx")]
+struct Formats {
+    header: Format,
+    text: Format,
+    date: Format,
+    datetime: Format,
+}
+
+#[cfg(feature = "xlsx")]
+pub fn write_xlsx(path: &Path, records: &[Record], columns: &[Field]) -> Result<()> {
+    let formats = Formats {
+        header: Format::new().set_bold(),
+        text: Format::new().set_num_format("@"),
+        date: Format::new().set_num_format("yyyy-mm-dd"),
+        datetime: Format::new().set_num_format("yyyy-mm-dd hh:mm:ss"),
+    };
+    let mut workbook = Workbook::new();
+
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("Records")?;
+    for (col, field) in columns.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, field.header(), &formats.header)?;
+    }
+    for (i, record) in records.iter().enumerate() {
+        for (col, &field) in columns.iter().enumerate() {
+            write_cell(sheet, i as u32 + 1, col as u16, record, field, &formats)?;
+        }
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    sheet.autofit();
+
+    write_summary(workbook.add_worksheet(), records, &formats)?;
+    workbook.save(path)?;
+    Ok(())
+}
+
+#[cfg(feature = "xlsx")]
+fn write_cell(
+    sheet: &mut Worksheet,
+    row: u32,
+    col: u16,
+    record: &Record,
+    field: Field,
+    formats: &Formats,
+) -> Result<()> {
+    match field {
+        Field::SerialDec => match record
+            .serial_dec
+            .as_deref()
+            .and_then(|s| s.parse::<u64>().ok())
+            .filter(|&n| n < EXCEL_MAX_INT)
+        {
+            Some(n) => sheet.write_number(row, col, n as f64)?,
+            None => sheet.write_string_with_format(row, col, record.field(field), &formats.text)?,
+        },
+        Field::Expiry => match record.expiry.as_deref().and_then(parse_date) {
+            Some(date) => sheet.write_datetime_with_format(row, col, date, &formats.date)?,
+            None => sheet.write_string_with_format(row, col, record.field(field), &formats.text)?,
+        },
+        Field::ManufactureDate => match record.manufacture_date.as_deref().and_then(parse_date) {
+            Some(date) => sheet.write_datetime_with_format(row, col, date, &formats.date)?,
+            None => sheet.write_string_with_format(row, col, record.field(field), &formats.text)?,
+        },
+        Field::ScanTime => sheet.write_datetime_with_format(
+            row,
+            col,
+            naive(record.scanned_at),
+            &formats.datetime,
+        )?,
+        Field::ReadTime => match record.read_at {
+            Some(t) => sheet.write_datetime_with_format(row, col, naive(t), &formats.datetime)?,
+            None => sheet,
+        },
+        _ => sheet.write_string_with_format(row, col, record.field(field), &formats.text)?,
+    };
+    Ok(())
+}
+
+#[cfg(feature = "xlsx")]
+fn naive(t: DateTime<Local>) -> chrono::NaiveDateTime {
+    t.naive_local()
+}
+
+#[cfg(feature = "xlsx")]
+#[derive(Default)]
+struct SessionCounts {
+    records: u32,
+    ok: u32,
+    failed: u32,
+    pending: u32,
+    mismatches: u32,
+    first: Option<DateTime<Local>>,
+    last: Option<DateTime<Local>>,
+}
+
+#[cfg(feature = "xlsx")]
+fn write_summary(sheet: &mut Worksheet, records: &[Record], formats: &Formats) -> Result<()> {
+    let mut sessions: BTreeMap<u64, SessionCounts> = BTreeMap::new();
+    for record in records {
+        let counts = sessions.entry(record.session_id).or_default();
+        counts.records += 1;
+        match record.status {
+            ReadStatus::Ok => counts.ok += 1,
+            ReadStatus::Pending => counts.pending += 1,
+            _ => counts.failed += 1,
+        }
+        if record.verification == Some(Verdict::Fail) {
+            counts.mismatches += 1;
+        }
+        counts.first = counts
+            .first
+            .min(Some(record.scanned_at))
+            .or(Some(record.scanned_at));
+        counts.last = counts.last.max(Some(record.scanned_at));
+    }
+
+    sheet.set_name("Summary")?;
+    let headers = [
+        "Session",
+        "Records",
+        "OK",
+        "Failed",
+        "Pending",
+        "Mismatches",
+        "First Scan",
+        "Last Scan",
+    ];
+    for (col, header) in headers.into_iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, header, &formats.header)?;
+    }
+    for (i, (session, counts)) in sessions.into_iter().enumerate() {
+        let row = i as u32 + 1;
+        sheet.write_number(row, 0, session as f64)?;
+        sheet.write_number(row, 1, counts.records)?;
+        sheet.write_number(row, 2, counts.ok)?;
+        sheet.write_number(row, 3, counts.failed)?;
+        sheet.write_number(row, 4, counts.pending)?;
+        sheet.write_number(row, 5, counts.mismatches)?;
+        for (col, t) in [(6, counts.first), (7, counts.last)] {
+            if let Some(t) = t {
+                sheet.write_datetime_with_format(row, col, naive(t), &formats.datetime)?;
+            }
+        }
+    }
+    sheet.autofit();
+    Ok(())
+}
+
+impl CsvOptions {
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        Grid::new("csv_options").num_columns(2).show(ui, |ui| {
+            ui.label("Delimiter");
+            ComboBox::from_id_source("delimiter")
+                .selected_text(format!("{:?}", self.delimiter))
+                .show_ui(ui, |ui| {
+                    for d in [Delimiter::Comma, Delimiter::Semicolon, Delimiter::Tab] {
+                        ui.selectable_value(&mut self.delimiter, d, format!("{:?}", d));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Line endings");
+            ComboBox::from_id_source("line_ending")
+                .selected_text(format!("{:?}", self.line_ending))
+                .show_ui(ui, |ui| {
+                    for l in [LineEnding::CrLf, LineEnding::Lf] {
+                        ui.selectable_value(&mut self.line_ending, l, format!("{:?}", l));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Encoding");
+            ComboBox::from_id_source("encoding")
+                .selected_text(encoding_name(self.encoding))
+                .show_ui(ui, |ui| {
+                    for e in [Encoding::Utf8Bom, Encoding::Utf8, Encoding::Utf16Le] {
+                        ui.selectable_value(&mut self.encoding, e, encoding_name(e));
+                    }
+                });
+            ui.end_row();
+        });
+
+        ui.label(RichText::new("Columns").strong());
+        columns_ui(ui, &mut self.columns);
+    }
+}
+
+#[cfg(feature = "gui")]
+fn encoding_name(encoding: Encoding) -> &'static str {
+    match encoding {
+        Encoding::Utf8 => "UTF-8",
+        Encoding::Utf8Bom => "UTF-8 with BOM",
+        Encoding::Utf16Le => "UTF-16 LE",
+    }
+}
+
+#[cfg(feature = "gui")]
+/// Checkboxes to pick columns and arrows to order them.
+pub fn columns_ui(ui: &mut Ui, columns: &mut Vec<Field>) {
+    let mut toggle = None;
+    let mut swap = None;
+    let unused = Field::ALL.into_iter().filter(|f| !columns.contains(f));
+    let fields: Vec<Field> = columns.iter().copied().chain(unused).collect();
+    for (i, field) in fields.into_iter().enumerate() {
+        ui.horizontal(|ui| {
+            let mut enabled = i < columns.len();
+            if ui.checkbox(&mut enabled, field.header()).changed() {
+                toggle = Some(field);
+            }
+            if i < columns.len() {
+                if ui.add_enabled(i > 0, Button::new("⬆").small()).clicked() {
+                    swap = Some((i - 1, i));
+                }
+                if ui
+                    .add_enabled(i + 1 < columns.len(), Button::new("⬇").small())
+                    .clicked()
+                {
+                    swap = Some((i, i + 1));
+                }
+            }
+        });
+    }
+    if let Some(field) = toggle {
+        match columns.iter().position(|&f| f == field) {
+            Some(i) => {
+                columns.remove(i);
+            }
+            None => columns.push(field),
+        }
+    }
+    if let Some((a, b)) = swap {
+        columns.swap(a, b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use super::*;
+
+    fn record(barcode: &str, note: Option<&str>) -> Record {
+        let mut record = Record::new(1, 1, barcode.into(), Local::now(), "L1".into(), "".into());
+        record.note = note.map(Into::into);
+        record
+    }
+
+    fn options(delimiter: Delimiter, columns: &[Field]) -> CsvOptions {
+        CsvOptions {
+            delimiter,
+            line_ending: LineEnding::Lf,
+            encoding: Encoding::Utf8,
+            columns: columns.to_vec(),
+        }
+    }
+
+    #[test]
+    fn quotes_delimiters_quotes_and_newlines() {
+        let records = [
+            record("A,1", Some("says \"hi\"")),
+            record("B;2", Some("two\nlines")),
+            record("C\t3", None),
+        ];
+        let columns = [Field::Barcode, Field::Note];
+        let comma = csv_rows(&records, &options(Delimiter::Comma, &columns), true).unwrap();
+        assert_eq!(
+            comma,
+            "Barcode,Note\n\"A,1\",\"says \"\"hi\"\"\"\nB;2,\"two\nlines\"\nC\t3,\n"
+        );
+        let semicolon =
+            csv_rows(&records, &options(Delimiter::Semicolon, &columns), false).unwrap();
+        assert_eq!(
+            semicolon,
+            "A,1;\"says \"\"hi\"\"\"\n\"B;2\";\"two\nlines\"\nC\t3;\n"
+        );
+        let tab = csv_rows(&records, &options(Delimiter::Tab, &columns), false).unwrap();
+        assert_eq!(
+            tab,
+            "A,1\t\"says \"\"hi\"\"\"\nB;2\t\"two\nlines\"\n\"C\t3\"\t\n"
+        );
+    }
+
+    #[test]
+    fn exports_chosen_columns_in_order() {
+        let records = [record("X1", Some("n"))];
+        let mut options = options(
+            Delimiter::Comma,
+            &[Field::Station, Field::Note, Field::Barcode],
+        );
+        options.line_ending = LineEnding::CrLf;
+        assert_eq!(
+            csv_rows(&records, &options, true).unwrap(),
+            "Station,Note,Barcode\r\nL1,n,X1\r\n"
+        );
+    }
+
+    #[test]
+    fn byte_order_mark_only_at_start_of_file() {
+        assert_eq!(encode("a", Encoding::Utf8Bom, true), b"\xEF\xBB\xBFa");
+        assert_eq!(encode("a", Encoding::Utf8Bom, false), b"a");
+        assert_eq!(encode("a", Encoding::Utf8, true), b"a");
+        assert_eq!(encode("a", Encoding::Utf16Le, true), [0xFF, 0xFE, b'a', 0]);
+        assert_eq!(encode("a", Encoding::Utf16Le, false), [b'a', 0]);
+
+        let records = [record("é", None)];
+        let mut options = options(Delimiter::Comma, &[Field::Barcode]);
+        options.encoding = Encoding::Utf8Bom;
+        assert_eq!(
+            to_csv(&records, &options).unwrap(),
+            "\u{feff}Barcode\né\n".as_bytes()
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::validation::mod10;
+
+/// Group separator, sent by scanners in place of FNC1.
+pub const GS: char = '\u{1d}';
+
+/// AIM symbology identifiers of GS1 symbols.
+const SYMBOLOGY_IDS: &[&str] = &["]C1", "]e0", "]d2", "]Q3", "]J1"];
+
+/// Application Identifiers of a GS1 barcode.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Gs1 {
+    pub gtin: Option<String>,
+    pub lot: Option<String>,
+    pub serial: Option<String>,
+    /// As `YYYY-MM-DD`.
+    pub expiry: Option<String>,
+    /// Every element string in order, including the ones above.
+    pub elements: Vec<(String, String)>,
+}
+
+/// Digits in the AI, by its first two digits.
+fn ai_length(prefix: &str) -> Option<usize> {
+    match prefix.parse::<u8>().ok()? {
+        0..=22 | 30 | 37 | 90..=99 => Some(2),
+        23..=25 | 40..=42 => Some(3),
+        31..=36 | 39 | 43 | 70..=72 | 80..=82 => Some(4),
+        _ => None,
+    }
+}
+
+/// Data length of AIs that are never followed by a separator.
+fn fixed_length(ai: &str) -> Option<usize> {
+    match &ai[..2] {
+        "00" => Some(18),
+        "01" | "02" | "03" => Some(14),
+        "04" => Some(16),
+        "11" | "12" | "13" | "15" | "16" | "17" => Some(6),
+        "20" => Some(2),
+        "31" | "32" | "33" | "34" | "35" | "36" => Some(6),
+        "41" => Some(13),
+        _ => None,
+    }
+}
+
+/// GS1 dates are YYMMDD, a day of 00 meaning the end of the month.
+fn parse_date(data: &str) -> Option<NaiveDate> {
+    let number = |range: std::ops::Range<usize>| data.get(range)?.parse::<u32>().ok();
+    let (year, month, day) = (2000 + number(0..2)? as i32, number(2..4)?, number(4..6)?);
+    match day {
+        0 => NaiveDate::from_ymd_opt(year, month, 1)?
+            .checked_add_months(chrono::Months::new(1))?
+            .pred_opt(),
+        day => NaiveDate::from_ymd_opt(year, month, day),
+    }
+    .filter(|d| d.year() < 2100)
+}
+
+/// `(01)09501101530003(10)AB-123` as printed under the symbol.
+fn parse_bracketed(barcode: &str) -> Option<Vec<(String, String)>> {
+    let mut elements = Vec::new();
+    let mut rest = barcode.strip_prefix('(')?;
+    while !rest.is_empty() {
+        let (ai, tail) = rest.split_once(')')?;
+        if ai.is_empty() || !ai.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let (data, tail) = tail.split_once('(').unwrap_or((tail, ""));
+        elements.push((ai.to_string(), data.to_string()));
+        rest = tail;
+    }
+    Some(elements)
+}
+
+/// Element strings as transmitted, variable length data ending at a GS.
+fn parse_raw(barcode: &str) -> Option<Vec<(String, String)>> {
+    let mut elements = Vec::new();
+    let mut rest = barcode.trim_start_matches(GS);
+    while !rest.is_empty() {
+        let ai = rest.get(..ai_length(rest.get(..2)?)?)?;
+        if !ai.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        rest = &rest[ai.len()..];
+        let data = match fixed_length(ai) {
+            Some(length) => rest.get(..length)?,
+            None => rest.split(GS).next().unwrap_or_default(),
+        };
+        if data.is_empty() {
+            return None;
+        }
+        elements.push((ai.to_string(), data.to_string()));
+        rest = rest[data.len()..].trim_start_matches(GS);
+    }
+    Some(elements)
+}
+
+/// Parses a GS1 barcode. Barcodes without a symbology identifier, separator
+/// or brackets are only taken for GS1 if they start with a valid GTIN, so
+/// plain serial numbers are left alone.
+pub fn parse(barcode: &str) -> Option<Gs1> {
+    let (marked, content) = match SYMBOLOGY_IDS.iter().find_map(|id| barcode.strip_prefix(id)) {
+        Some(content) => (true, content),
+        None => (barcode.contains(GS), barcode),
+    };
+    let elements = match parse_bracketed(content) {
+        Some(elements) => elements,
+        None => parse_raw(content)?,
+    };
+    let mut gs1 = Gs1::default();
+    for (ai, data) in &elements {
+        match ai.as_str() {
+            "01" => gs1.gtin = Some(data.clone()),
+            "10" => gs1.lot = Some(data.clone()),
+            "21" => gs1.serial = Some(data.clone()),
+            "17" => gs1.expiry = parse_date(data).map(|d| d.to_string()),
+            _ => {}
+        }
+    }
+    let bracketed = content.starts_with('(');
+    let starts_with_gtin = elements
+        .first()
+        .is_some_and(|(ai, data)| ai == "01" && mod10(data));
+    if elements.is_empty() || !(marked || bracketed || starts_with_gtin) {
+        return None;
+    }
+    gs1.elements = elements;
+    Some(gs1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elements(gs1: &Gs1) -> Vec<(&str, &str)> {
+        gs1.elements
+            .iter()
+            .map(|(ai, data)| (ai.as_str(), data.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_bracketed_raw_and_aim_prefixed() {
+        let expected = Gs1 {
+            gtin: Some("09501101530003".into()),
+            lot: Some("AB-123".into()),
+            serial: Some("S1".into()),
+            expiry: Some("2025-12-31".into()),
+            elements: vec![
+                ("01".into(), "09501101530003".into()),
+                ("17".into(), "251231".into()),
+                ("10".into(), "AB-123".into()),
+                ("21".into(), "S1".into()),
+            ],
+        };
+        for barcode in [
+            "(01)09501101530003(17)251231(10)AB-123(21)S1".to_string(),
+            format!("010950110153000317251231{GS}10AB-123{GS}21S1"),
+            format!("{GS}010950110153000317251231{GS}10AB-123{GS}21S1"),
+            format!("]C1010950110153000317251231{GS}10AB-123{GS}21S1"),
+            format!("]d2010950110153000317251231{GS}10AB-123{GS}21S1"),
+        ] {
+            assert_eq!(parse(&barcode).as_ref(), Some(&expected), "{barcode:?}");
+        }
+    }
+
+    #[test]
+    fn fixed_length_ais_need_no_separator() {
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            (
+                "]C100123456789012345675010950110153000321X",
+                &[
+                    ("00", "123456789012345675"),
+                    ("01", "09501101530003"),
+                    ("21", "X"),
+                ],
+            ),
+            (
+                "]C1112401011224020113240301152404011624050117240601",
+                &[
+                    ("11", "240101"),
+                    ("12", "240201"),
+                    ("13", "240301"),
+                    ("15", "240401"),
+                    ("16", "240501"),
+                    ("17", "240601"),
+                ],
+            ),
+            (
+                "]C10109501101530003310300050031220012503922999",
+                &[
+                    ("01", "09501101530003"),
+                    ("3103", "000500"),
+                    ("3122", "001250"),
+                    ("3922", "999"),
+                ],
+            ),
+        ];
+        for (barcode, expected) in cases {
+            let gs1 = parse(barcode).unwrap_or_else(|| panic!("{barcode:?} not parsed"));
+            assert_eq!(elements(&gs1), *expected, "{barcode:?}");
+        }
+    }
+
+    #[test]
+    fn day_00_is_end_of_month() {
+        for (data, expected) in [
+            ("250200", Some("2025-02-28")),
+            ("240200", Some("2024-02-29")),
+            ("241200", Some("2024-12-31")),
+            ("250431", None),
+            ("251300", None),
+            ("2502", None),
+        ] {
+            assert_eq!(
+                parse_date(data).map(|d| d.to_string()).as_deref(),
+                expected,
+                "{data}"
+            );
+        }
+        let gs1 = parse("(01)09501101530003(17)250200").unwrap();
+        assert_eq!(gs1.expiry.as_deref(), Some("2025-02-28"));
+    }
+
+    #[test]
+    fn plain_serials_are_not_gs1() {
+        for barcode in [
+            "",
+            "123456789",
+            "SN-000123",
+            "0012345678",
+            // An AI 01 whose GTIN check digit is wrong.
+            "0109501101530004",
+            "1012345",
+            "(01",
+            "(AB)123",
+        ] {
+            assert_eq!(parse(barcode), None, "{barcode:?}");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{record::Record, store::Store};
+
+const DATA_DIR: &str = "sn-tracer";
+const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// A journal line, a record's new state or its deletion.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Deleted { deleted: u64 },
+    Record(Box<Record>),
+}
+
+/// Append-only log of every record change, synced as it happens so neither a
+/// crash nor a failed database write loses a scan.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// A database write failed, so the journal has changes it lacks.
+    behind: bool,
+}
+
+pub fn default_path() -> Result<PathBuf> {
+    Ok(dirs::data_dir()
+        .context("No data directory")?
+        .join(DATA_DIR)
+        .join(JOURNAL_FILE))
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open journal {:?}", path))?;
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            behind: false,
+        })
+    }
+
+    pub fn append(&mut self, record: &Record) -> Result<()> {
+        self.write(&serde_json::to_string(record)?)
+    }
+
+    pub fn delete(&mut self, id: u64) -> Result<()> {
+        self.write(&serde_json::to_string(&Entry::Deleted { deleted: id })?)
+    }
+
+    fn write(&mut self, line: &str) -> Result<()> {
+        self.file.write_all(format!("{line}\n").as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Called once the database has taken the change just journalled, or
+    /// failed to. The journal is emptied while the database keeps up, and
+    /// kept from the first failure on, to be recovered at the next start.
+    pub fn stored(&mut self, ok: bool) -> Result<()> {
+        self.behind |= !ok;
+        if !self.behind {
+            self.truncate()?;
+        }
+        Ok(())
+    }
+
+    /// Latest state of every journalled record, oldest first, and the ids of
+    /// deleted ones. A torn final line from a crash mid-write is skipped.
+    pub fn replay(&self) -> Result<(Vec<Record>, Vec<u64>)> {
+        let mut records: HashMap<u64, Record> = HashMap::new();
+        let mut deleted = HashSet::new();
+        let reader = BufReader::new(File::open(&self.path)?);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Entry>(&line) {
+                Ok(Entry::Record(record)) => {
+                    // Restored by an undo.
+                    deleted.remove(&record.id);
+                    records.insert(record.id, *record);
+                }
+                Ok(Entry::Deleted { deleted: id }) => {
+                    records.remove(&id);
+                    deleted.insert(id);
+                }
+                Err(e) => warn!("Skipping journal line {}: {:?}", i + 1, e),
+            }
+        }
+        let mut records: Vec<Record> = records.into_values().collect();
+        records.sort_by_key(|r| r.id);
+        let mut deleted: Vec<u64> = deleted.into_iter().collect();
+        deleted.sort();
+        Ok((records, deleted))
+    }
+
+    /// Writes journalled records into `store`, then empties the journal.
+    pub fn recover(&mut self, store: &Store) -> Result<usize> {
+        let (records, deleted) = self.replay()?;
+        for record in &records {
+            store.upsert(record)?;
+        }
+        for &id in &deleted {
+            store.delete(id)?;
+        }
+        self.truncate()?;
+        self.behind = false;
+        Ok(records.len())
+    }
+
+    /// Empties the journal once its contents are safely in the database.
+    pub fn truncate(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use super::*;
+
+    fn journal(name: &str) -> Journal {
+        let path = std::env::temp_dir()
+            .join(format!("sn-tracer-test-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_file(&path);
+        Journal::open(&path).unwrap()
+    }
+
+    fn record(id: u64, barcode: &str) -> Record {
+        Record::new(id, 1, barcode.into(), Local::now(), "".into(), "".into())
+    }
+
+    fn lines(journal: &Journal) -> usize {
+        std::fs::read_to_string(&journal.path)
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    #[test]
+    fn replays_latest_state_and_deletions() {
+        let mut journal = journal("replay.jsonl");
+        journal.append(&record(2, "B")).unwrap();
+        journal.append(&record(1, "A")).unwrap();
+        journal.append(&record(2, "B2")).unwrap();
+        journal.append(&record(3, "C")).unwrap();
+        journal.delete(3).unwrap();
+        journal.delete(1).unwrap();
+        journal.append(&record(1, "A")).unwrap();
+        journal.write("{\"id\": 4, \"barc").unwrap();
+
+        let (records, deleted) = journal.replay().unwrap();
+        let barcodes: Vec<_> = records.iter().map(|r| r.barcode.as_str()).collect();
+        assert_eq!(barcodes, ["A", "B2"]);
+        assert_eq!(deleted, [3]);
+    }
+
+    #[test]
+    fn stays_empty_while_the_database_keeps_up() {
+        let mut journal = journal("stored.jsonl");
+        journal.append(&record(1, "A")).unwrap();
+        journal.stored(true).unwrap();
+        assert_eq!(lines(&journal), 0);
+
+        journal.append(&record(2, "B")).unwrap();
+        journal.stored(false).unwrap();
+        journal.append(&record(3, "C")).unwrap();
+        journal.stored(true).unwrap();
+        assert_eq!(lines(&journal), 2);
+
+        let store = Store::open_in_memory().unwrap();
+        store.new_session("", "").unwrap();
+        assert_eq!(journal.recover(&store).unwrap(), 2);
+        assert_eq!(lines(&journal), 0);
+        journal.append(&record(4, "D")).unwrap();
+        journal.stored(true).unwrap();
+        assert_eq!(lines(&journal), 0);
+    }
+}
@@ -8,7 +8,7 @@ fn main() {
     eframe::run_native(
         "sn-tracer",
         options,
-        Box::new(move |cc: &eframe::CreationContext| Box::new(App::new(&cc))),
+        Box::new(move |cc: &eframe::CreationContext| Box::new(App::new(cc))),
     )
     .expect("Failed to launch");
 }
@@ -0,0 +1,233 @@
+#[cfg(feature = "mqtt")]
+use std::time::Duration;
+
+#[cfg(feature = "mqtt")]
+use chrono::Local;
+#[cfg(feature = "gui")]
+use egui::*;
+#[cfg(feature = "mqtt")]
+use log::*;
+#[cfg(feature = "mqtt")]
+use rumqttc::{Client, LastWill, MqttOptions, QoS};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "mqtt")]
+use serde_json::json;
+
+#[cfg(feature = "mqtt")]
+use crate::record::Record;
+
+#[cfg(feature = "mqtt")]
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+#[cfg(feature = "mqtt")]
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+#[cfg(feature = "mqtt")]
+const QUEUE: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Qos {
+    AtMostOnce,
+    AtLeastOnce,
+    ExactlyOnce,
+}
+
+#[cfg(feature = "mqtt")]
+impl From<Qos> for QoS {
+    fn from(value: Qos) -> Self {
+        match value {
+            Qos::AtMostOnce => Self::AtMostOnce,
+            Qos::AtLeastOnce => Self::AtLeastOnce,
+            Qos::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
+
+/// Topics may contain `{station}`, replaced by the station ID.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct MqttSettings {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub qos: Qos,
+    pub record_topic: String,
+    pub status_topic: String,
+    pub scanner_topic: String,
+    /// Keeps the last status on the broker for dashboards that connect later.
+    pub retain_status: bool,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".into(),
+            port: 1883,
+            username: None,
+            password: None,
+            qos: Qos::AtLeastOnce,
+            record_topic: "sn-tracer/{station}/records".into(),
+            status_topic: "sn-tracer/{station}/status".into(),
+            scanner_topic: "sn-tracer/{station}/scanner".into(),
+            retain_status: true,
+        }
+    }
+}
+
+impl MqttSettings {
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "Publish records and station status");
+        Grid::new("mqtt").num_columns(2).show(ui, |ui| {
+            ui.label("Broker");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.host);
+                ui.add(DragValue::new(&mut self.port));
+            });
+            ui.end_row();
+
+            ui.label("Username");
+            optional_text(ui, &mut self.username, false);
+            ui.end_row();
+
+            ui.label("Password");
+            optional_text(ui, &mut self.password, true);
+            ui.end_row();
+
+            ui.label("QoS");
+            ComboBox::from_id_source("mqtt_qos")
+                .selected_text(format!("{:?}", self.qos))
+                .show_ui(ui, |ui| {
+                    for q in [Qos::AtMostOnce, Qos::AtLeastOnce, Qos::ExactlyOnce] {
+                        ui.selectable_value(&mut self.qos, q, format!("{:?}", q));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Record topic");
+            ui.text_edit_singleline(&mut self.record_topic);
+            ui.end_row();
+
+            ui.label("Status topic");
+            ui.text_edit_singleline(&mut self.status_topic);
+            ui.end_row();
+
+            ui.label("Scanner topic");
+            ui.text_edit_singleline(&mut self.scanner_topic);
+            ui.end_row();
+        });
+        ui.checkbox(&mut self.retain_status, "Retain status");
+    }
+}
+
+#[cfg(feature = "gui")]
+fn optional_text(ui: &mut Ui, value: &mut Option<String>, password: bool) {
+    let mut text = value.clone().unwrap_or_default();
+    if ui
+        .add(TextEdit::singleline(&mut text).password(password))
+        .changed()
+    {
+        *value = Some(text).filter(|t| !t.is_empty());
+    }
+}
+
+#[cfg(feature = "mqtt")]
+/// Publishes to the broker from a background connection that reconnects on
+/// its own. Publishing never blocks, messages are dropped when the queue is
+/// full.
+pub struct Mqtt {
+    client: Client,
+    settings: MqttSettings,
+    station: String,
+}
+
+#[cfg(feature = "mqtt")]
+impl Mqtt {
+    pub fn start(settings: &MqttSettings, station: &str) -> Self {
+        let topic = |t: &str| t.replace("{station}", station);
+        let mut options = MqttOptions::new(
+            format!("sn-tracer-{}-{}", station, std::process::id()),
+            &settings.host,
+            settings.port,
+        );
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(
+            topic(&settings.status_topic),
+            json!({ "connection": "Offline", "station": station }).to_string(),
+            settings.qos.into(),
+            settings.retain_status,
+        ));
+        if let Some(username) = &settings.username {
+            options.set_credentials(username, settings.password.clone().unwrap_or_default());
+        }
+        let (client, mut connection) = Client::new(options, QUEUE);
+        std::thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    Ok(event) => trace!("MQTT: {:?}", event),
+                    Err(e) => {
+                        warn!("MQTT connection failed: {}", e);
+                        std::thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+            debug!("MQTT stopped");
+        });
+        Self {
+            client,
+            settings: settings.clone(),
+            station: station.to_string(),
+        }
+    }
+
+    fn publish(&mut self, topic: String, retain: bool, payload: String) {
+        let topic = topic.as_str().replace("{station}", &self.station);
+        if let Err(e) = self
+            .client
+            .try_publish(topic, self.settings.qos.into(), retain, payload)
+        {
+            warn!("MQTT publish failed: {}", e);
+        }
+    }
+
+    pub fn publish_record(&mut self, record: &Record) {
+        match serde_json::to_string(record) {
+            Ok(payload) => self.publish(self.settings.record_topic.clone(), false, payload),
+            Err(e) => error!("Failed to serialize record: {:?}", e),
+        }
+    }
+
+    pub fn publish_status(&mut self, connection: &str, port: Option<&str>) {
+        let payload = json!({
+            "connection": connection,
+            "port": port,
+            "station": self.station,
+            "at": Local::now(),
+        });
+        self.publish(
+            self.settings.status_topic.clone(),
+            self.settings.retain_status,
+            payload.to_string(),
+        );
+    }
+
+    pub fn publish_scanner_failed(&mut self) {
+        let payload = json!({
+            "event": "ScannerStartFail",
+            "station": self.station,
+            "at": Local::now(),
+        });
+        let topic = self.settings.scanner_topic.clone();
+        self.publish(topic, false, payload.to_string());
+    }
+}
+
+#[cfg(feature = "mqtt")]
+impl Drop for Mqtt {
+    /// A clean disconnect skips the last will, so say goodbye first.
+    fn drop(&mut self) {
+        self.publish_status("Offline", None);
+        let _ = self.client.try_disconnect();
+    }
+}
@@ -0,0 +1,449 @@
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::gs1::{self, GS};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%d/%m/%Y", "%d-%m-%Y", "%Y%m%d"];
+
+pub fn parse_date(s: &str) -> Option<NaiveDate> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|f| NaiveDate::parse_from_str(s.trim(), f).ok())
+}
+
+/// A `read` reply, `<hex serial>,<decimal serial>,<manufacture date>`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub serial_hex: String,
+    pub serial_dec: u64,
+    pub manufacture_date: NaiveDate,
+}
+
+impl DeviceInfo {
+    /// Parses a reply, refusing anything that doesn't hold together so
+    /// line noise is never taken for a reading.
+    pub fn parse(reply: &str) -> Result<Self> {
+        let fields: Vec<&str> = reply.trim().split(',').map(str::trim).collect();
+        let [hex, dec, date] = fields[..] else {
+            bail!("Expected 3 fields, got {}", fields.len());
+        };
+        let digits = hex
+            .strip_prefix("0x")
+            .or(hex.strip_prefix("0X"))
+            .unwrap_or(hex);
+        let hex_value = u64::from_str_radix(digits, 16)
+            .with_context(|| format!("Invalid hex serial {hex:?}"))?;
+        let serial_dec: u64 = dec
+            .parse()
+            .with_context(|| format!("Invalid decimal serial {dec:?}"))?;
+        ensure!(
+            hex_value == serial_dec,
+            "Hex serial {hex} is {hex_value}, not {serial_dec}"
+        );
+        let manufacture_date =
+            parse_date(date).with_context(|| format!("Invalid manufacture date {date:?}"))?;
+        ensure!(
+            manufacture_date <= Local::now().date_naive(),
+            "Manufacture date {manufacture_date} is in the future"
+        );
+        Ok(Self {
+            serial_hex: hex.to_string(),
+            serial_dec,
+            manufacture_date,
+        })
+    }
+}
+
+/// A column of the table and of exports.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Barcode,
+    Gtin,
+    Lot,
+    Gs1Serial,
+    Expiry,
+    SerialHex,
+    SerialDec,
+    ManufactureDate,
+    Status,
+    Error,
+    Verification,
+    ScanTime,
+    ReadTime,
+    Port,
+    Station,
+    Operator,
+    Note,
+    DefectCode,
+}
+
+impl Field {
+    pub const ALL: [Field; 18] = [
+        Field::Barcode,
+        Field::Gtin,
+        Field::Lot,
+        Field::Gs1Serial,
+        Field::Expiry,
+        Field::SerialHex,
+        Field::SerialDec,
+        Field::ManufactureDate,
+        Field::Status,
+        Field::Error,
+        Field::Verification,
+        Field::ScanTime,
+        Field::ReadTime,
+        Field::Port,
+        Field::Station,
+        Field::Operator,
+        Field::Note,
+        Field::DefectCode,
+    ];
+
+    pub fn header(self) -> &'static str {
+        match self {
+            Field::Barcode => "Barcode",
+            Field::Gtin => "GTIN",
+            Field::Lot => "Lot",
+            Field::Gs1Serial => "GS1 Serial",
+            Field::Expiry => "Expiry",
+            Field::SerialHex => "Serial Number (HEX)",
+            Field::SerialDec => "Serial Number (DEC)",
+            Field::ManufactureDate => "Manufacture Date",
+            Field::Status => "Status",
+            Field::Error => "Error",
+            Field::Verification => "Verification",
+            Field::ScanTime => "Scan Time",
+            Field::ReadTime => "Read Time",
+            Field::Port => "Port",
+            Field::Station => "Station",
+            Field::Operator => "Operator",
+            Field::Note => "Note",
+            Field::DefectCode => "Defect Code",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadStatus {
+    Pending,
+    Ok,
+    /// The device didn't answer in time.
+    Timeout,
+    /// No device, or it went away during the read.
+    NotConnected,
+    /// The device answered with something that isn't a reading. Failures
+    /// recorded before they were told apart load as this.
+    #[serde(alias = "Error")]
+    ProtocolError,
+}
+
+impl ReadStatus {
+    pub const ALL: [ReadStatus; 5] = [
+        ReadStatus::Pending,
+        ReadStatus::Ok,
+        ReadStatus::Timeout,
+        ReadStatus::NotConnected,
+        ReadStatus::ProtocolError,
+    ];
+
+    pub fn is_failure(self) -> bool {
+        !matches!(self, ReadStatus::Pending | ReadStatus::Ok)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReadStatus::Pending => "Pending",
+            ReadStatus::Ok => "OK",
+            ReadStatus::Timeout => "Timeout",
+            ReadStatus::NotConnected => "Not connected",
+            ReadStatus::ProtocolError => "Protocol error",
+        }
+    }
+}
+
+/// Whether the label matches the serial the device reported.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Fail,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateKind {
+    /// The barcode was scanned before.
+    Barcode,
+    /// The device reported the same serial for another barcode.
+    Serial,
+}
+
+/// An earlier record this one repeats.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Duplicate {
+    pub of: u64,
+    pub session_id: u64,
+    pub kind: DuplicateKind,
+}
+
+/// One scan and the device reading taken for it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Record {
+    pub id: u64,
+    #[serde(default)]
+    pub session_id: u64,
+    pub barcode: String,
+    /// Parsed from GS1 barcodes.
+    #[serde(default)]
+    pub gtin: Option<String>,
+    #[serde(default)]
+    pub lot: Option<String>,
+    #[serde(default)]
+    pub gs1_serial: Option<String>,
+    #[serde(default)]
+    pub expiry: Option<String>,
+    pub serial_hex: Option<String>,
+    pub serial_dec: Option<String>,
+    pub manufacture_date: Option<String>,
+    pub status: ReadStatus,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub verification: Option<Verdict>,
+    #[serde(default)]
+    pub duplicate: Option<Duplicate>,
+    #[serde(default = "Local::now")]
+    pub scanned_at: DateTime<Local>,
+    #[serde(default)]
+    pub read_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub port: Option<String>,
+    #[serde(default)]
+    pub station: String,
+    #[serde(default)]
+    pub operator: String,
+    /// Added by the operator.
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub defect_code: Option<String>,
+}
+
+impl Record {
+    pub fn new(
+        id: u64,
+        session_id: u64,
+        barcode: String,
+        scanned_at: DateTime<Local>,
+        station: String,
+        operator: String,
+    ) -> Self {
+        let gs1 = gs1::parse(&barcode).unwrap_or_default();
+        Self {
+            id,
+            session_id,
+            barcode,
+            gtin: gs1.gtin,
+            lot: gs1.lot,
+            gs1_serial: gs1.serial,
+            expiry: gs1.expiry,
+            serial_hex: None,
+            serial_dec: None,
+            manufacture_date: None,
+            status: ReadStatus::Pending,
+            error: None,
+            verification: None,
+            duplicate: None,
+            scanned_at,
+            read_at: None,
+            port: None,
+            station,
+            operator,
+            note: None,
+            defect_code: None,
+        }
+    }
+
+    /// Replaces a mistyped barcode along with what was parsed from it.
+    pub fn set_barcode(&mut self, barcode: String) {
+        let gs1 = gs1::parse(&barcode).unwrap_or_default();
+        self.barcode = barcode;
+        self.gtin = gs1.gtin;
+        self.lot = gs1.lot;
+        self.gs1_serial = gs1.serial;
+        self.expiry = gs1.expiry;
+    }
+
+    /// Takes over what an operator's edit from `from` to `to` changed, so
+    /// undoing or redoing it leaves readings taken since untouched.
+    pub fn apply_edit(&mut self, from: &Record, to: &Record) {
+        if from.barcode != to.barcode {
+            self.set_barcode(to.barcode.clone());
+        }
+        if from.note != to.note {
+            self.note = to.note.clone();
+        }
+        if from.defect_code != to.defect_code {
+            self.defect_code = to.defect_code.clone();
+        }
+        if from.duplicate != to.duplicate {
+            self.duplicate = to.duplicate;
+        }
+    }
+
+    pub fn apply_read(&mut self, info: &DeviceInfo, port: Option<String>) {
+        self.serial_hex = Some(info.serial_hex.clone());
+        self.serial_dec = Some(info.serial_dec.to_string());
+        self.manufacture_date = Some(info.manufacture_date.to_string());
+        self.status = ReadStatus::Ok;
+        self.error = None;
+        self.verification = None;
+        self.read_at = Some(Local::now());
+        self.port = port;
+        self.forget_serial_duplicate();
+    }
+
+    /// A failed re-read keeps the reading the record already has, along with
+    /// when and where it was taken.
+    pub fn apply_error(&mut self, status: ReadStatus, error: &str, port: Option<String>) {
+        self.status = status;
+        self.error = Some(error.trim().to_string());
+        if self.serial_hex.is_none() {
+            self.read_at = Some(Local::now());
+            self.port = port;
+        }
+    }
+
+    /// A serial duplicate was judged from the previous reading.
+    fn forget_serial_duplicate(&mut self) {
+        if self
+            .duplicate
+            .is_some_and(|d| d.kind == DuplicateKind::Serial)
+        {
+            self.duplicate = None;
+        }
+    }
+
+    pub fn status_text(&self) -> String {
+        match self.status {
+            ReadStatus::Pending => "Reading...".into(),
+            ReadStatus::Ok => "OK".into(),
+            status => format!("FAILED - {}", status.name()),
+        }
+    }
+
+    pub fn field(&self, field: Field) -> String {
+        let text = |f: &Option<String>| f.clone().unwrap_or_default();
+        match field {
+            Field::Barcode => self.barcode.replace(GS, "<GS>"),
+            Field::Gtin => text(&self.gtin),
+            Field::Lot => text(&self.lot),
+            Field::Gs1Serial => text(&self.gs1_serial),
+            Field::Expiry => text(&self.expiry),
+            Field::SerialHex => text(&self.serial_hex),
+            Field::SerialDec => text(&self.serial_dec),
+            Field::ManufactureDate => text(&self.manufacture_date),
+            Field::Status => self.status_text(),
+            Field::Error => text(&self.error),
+            Field::Verification => match self.verification {
+                Some(Verdict::Pass) => "PASS".into(),
+                Some(Verdict::Fail) => "FAIL".into(),
+                None => String::new(),
+            },
+            Field::ScanTime => self.scanned_at.format(TIME_FORMAT).to_string(),
+            Field::ReadTime => self
+                .read_at
+                .map(|t| t.format(TIME_FORMAT).to_string())
+                .unwrap_or_default(),
+            Field::Port => text(&self.port),
+            Field::Station => self.station.clone(),
+            Field::Operator => self.operator.clone(),
+            Field::Note => text(&self.note),
+            Field::DefectCode => text(&self.defect_code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_device_info() {
+        let info = DeviceInfo::parse(" 0x1F, 31 ,2023-05-04\r\n").unwrap();
+        assert_eq!(
+            info,
+            DeviceInfo {
+                serial_hex: "0x1F".into(),
+                serial_dec: 31,
+                manufacture_date: NaiveDate::from_ymd_opt(2023, 5, 4).unwrap(),
+            }
+        );
+        assert_eq!(
+            DeviceInfo::parse("ff,255,04/05/2023").unwrap().serial_dec,
+            255
+        );
+    }
+
+    #[test]
+    fn undoing_an_edit_keeps_later_readings() {
+        let mut record = Record::new(1, 1, "SN-1".into(), Local::now(), "".into(), "".into());
+        record.apply_error(ReadStatus::Timeout, "Connection timeout", None);
+        let before = record.clone();
+        record.set_barcode("SN-2".into());
+        record.note = Some("relabelled".into());
+        let after = record.clone();
+        let info = DeviceInfo::parse("0x2,2,2023-05-04").unwrap();
+        record.apply_read(&info, Some("ttyUSB0".into()));
+
+        record.apply_edit(&after, &before);
+        assert_eq!(record.barcode, "SN-1");
+        assert_eq!(record.note, None);
+        assert_eq!(record.status, ReadStatus::Ok);
+        assert_eq!(record.serial_dec.as_deref(), Some("2"));
+
+        record.apply_edit(&before, &after);
+        assert_eq!(record.barcode, "SN-2");
+        assert_eq!(record.note.as_deref(), Some("relabelled"));
+        assert_eq!(record.status, ReadStatus::Ok);
+    }
+
+    #[test]
+    fn failed_reread_keeps_the_reading() {
+        let mut record = Record::new(1, 1, "SN-1".into(), Local::now(), "".into(), "".into());
+        let info = DeviceInfo::parse("0x2,2,2023-05-04").unwrap();
+        record.apply_read(&info, Some("ttyUSB0".into()));
+        let read_at = record.read_at;
+
+        record.apply_error(ReadStatus::Timeout, "Connection timeout\r\n", None);
+        assert_eq!(record.status, ReadStatus::Timeout);
+        assert_eq!(record.error.as_deref(), Some("Connection timeout"));
+        assert_eq!(record.serial_hex.as_deref(), Some("0x2"));
+        assert_eq!(record.serial_dec.as_deref(), Some("2"));
+        assert_eq!(record.manufacture_date.as_deref(), Some("2023-05-04"));
+        assert_eq!(record.read_at, read_at);
+        assert_eq!(record.port.as_deref(), Some("ttyUSB0"));
+
+        record.apply_read(&info, None);
+        assert_eq!(record.status, ReadStatus::Ok);
+        assert_eq!(record.error, None);
+    }
+
+    #[test]
+    fn refuses_malformed_device_info() {
+        let tomorrow = Local::now().date_naive().succ_opt().unwrap();
+        for reply in [
+            "".to_string(),
+            "connected".to_string(),
+            "0x1F,31".to_string(),
+            "0x1F,31,2023-05-04,extra".to_string(),
+            // Hex and decimal serials disagree.
+            "0x1F,30,2023-05-04".to_string(),
+            "0xZZ,31,2023-05-04".to_string(),
+            "0x1F,-31,2023-05-04".to_string(),
+            "0x1F,31,2023-02-30".to_string(),
+            format!("0x1F,31,{tomorrow}"),
+        ] {
+            assert!(DeviceInfo::parse(&reply).is_err(), "{reply:?}");
+        }
+    }
+}
@@ -1,27 +1,31 @@
-use std::{path::PathBuf, process::Stdio};
+use std::{path::PathBuf, process::Stdio, sync::Arc};
 
-use anyhow::{bail, Context, Result};
-use eframe::egui;
-use itertools::Itertools;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
 use log::*;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    sync::mpsc::UnboundedSender,
-    time::{interval, timeout, Duration},
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
 };
-use tokio_serial::{SerialPort, SerialStream};
 
-use crate::HEADERS;
+use crate::{
+    export::{self, CsvOptions},
+    record::{DeviceInfo, ReadStatus, Record},
+    settings::DeviceSettings,
+    transport::{self, Connector, PortInfo, Transport},
+};
 
-const ERROR: &str = "Channel closed";
-const TIMEOUT_MS: u64 = 1000;
-const APPROVED_PIDS: &[u16] = &[24577, 29987];
 #[cfg(target_family = "windows")]
 const SCANNER_EXE_NAME: &str = "scanner.exe";
 #[cfg(target_family = "unix")]
 const SCANNER_EXE_NAME: &str = "scanner";
+const SCANNER_ENV: &str = "SCANNER_PATH";
 
+/// `SCANNER_PATH` if set, else the scanner next to this executable.
 fn get_scanner_path() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os(SCANNER_ENV) {
+        return Ok(path.into());
+    }
     let mut path = std::fs::canonicalize(std::env::current_exe()?)?;
     path.pop();
     path.push(SCANNER_EXE_NAME);
@@ -31,86 +35,33 @@ fn get_scanner_path() -> Result<PathBuf> {
 #[derive(Debug, Clone)]
 pub enum Command {
     Connect,
-    Read,
-    Download(PathBuf, Vec<String>, Vec<String>),
+    Read(u64),
+    Download(PathBuf, Vec<Record>, CsvOptions),
     StopScanner,
     StartScanner,
     CheckConnection,
+    ApplySettings(DeviceSettings),
+    ListPorts,
 }
 
 #[derive(Debug, Clone)]
 pub enum Reply {
     Connected(String),
     Connecting,
-    Read(String),
-    ReadError(String),
+    Read(u64, DeviceInfo),
+    ReadError(u64, ReadStatus, String),
     Disconnected,
     DownloadError(String),
-    BarcodeOutput(String),
+    BarcodeOutput(String, DateTime<Local>),
     ScannerStartFail,
+    Ports(Vec<PortInfo>),
 }
 
-fn get_available_devices() -> Vec<String> {
-    let devices = tokio_serial::available_ports().unwrap_or(Vec::new());
-    devices
-        .into_iter()
-        .filter(|d| {
-            if let tokio_serial::SerialPortType::UsbPort(ref info) = d.port_type {
-                debug!("Detected: {}, {:?}", d.port_name, info);
-                return APPROVED_PIDS.contains(&info.pid);
-            }
-            false
-        })
-        .map(|d| d.port_name)
-        .collect()
-}
-
-async fn autoconnect(send_channel: &UnboundedSender<Reply>) -> Result<BufReader<SerialStream>> {
-    let devices = get_available_devices();
-    if !devices.is_empty() {
-        send_channel.send(Reply::Connecting).expect(ERROR);
-    }
-    let mut futures = tokio::task::JoinSet::new();
-    devices.into_iter().for_each(|d| {
-        futures.spawn(try_connect(d));
-    });
-    debug!("Connection attempts: {}", futures.len());
-    let mut results = Vec::new();
-    while let Some(Ok(res)) = futures.join_next().await {
-        results.push(res);
-    }
-    results
-        .into_iter()
-        .map(|r| {
-            debug!("{:?}", r);
-            r
-        })
-        .find_map(Result::ok)
-        .context("Failed to connect to available devices")
-}
-
-async fn try_connect(device: String) -> Result<BufReader<SerialStream>> {
-    let handle = SerialStream::open(&tokio_serial::new(&device, 9600))?;
-    let mut handle = BufReader::new(handle);
-    debug!("Handle obtained: {:?}", handle);
-    for _ in 0..20 {
-        if let Ok(true) = check_connection(&mut handle).await {
-            debug!("Connected to {:?}", device);
-            return Ok(handle);
-        }
-        tokio::time::sleep(Duration::from_millis(TIMEOUT_MS)).await;
-    }
-    bail!("Could not establish handshake with {:?}", handle)
-}
-
-async fn listen(
-    channel: tokio::sync::mpsc::UnboundedSender<Reply>,
-    ctx: egui::Context,
-) -> Result<()> {
+async fn listen(replies: Replies) -> Result<()> {
     let scanner_path = get_scanner_path()?;
     debug!("Scanner path: {:?}", scanner_path);
     let mut scanner = tokio::process::Command::new(scanner_path)
-        .args(&["--parent", &std::process::id().to_string()])
+        .args(["--parent", &std::process::id().to_string()])
         .kill_on_drop(true)
         .stdout(Stdio::piped())
         .spawn()?;
@@ -123,162 +74,224 @@ async fn listen(
     let mut buf = String::new();
     debug!("Started scanner");
     loop {
-        output.read_line(&mut buf).await?;
+        if output.read_line(&mut buf).await? == 0 {
+            bail!("Scanner exited: {:?}", scanner.try_wait());
+        }
         debug!("Scanner output: {}", buf);
-        if let Err(_) = channel.send(Reply::BarcodeOutput(buf.trim().to_string())) {
-            scanner.kill().await.unwrap();
+        let barcode = buf.trim();
+        if !barcode.is_empty()
+            && !replies.send(Reply::BarcodeOutput(barcode.to_string(), Local::now()))
+        {
+            scanner.kill().await?;
+            return Ok(());
         }
-        ctx.request_repaint();
         buf.clear();
     }
 }
 
-fn start_listen_task(
-    channel: tokio::sync::mpsc::UnboundedSender<Reply>,
-    ctx: egui::Context,
-) -> tokio::task::JoinHandle<()> {
+fn start_listen_task(replies: Replies) -> tokio::task::JoinHandle<()> {
     tokio::spawn({
         async move {
-            let channel_2 = channel.clone();
-            if let Err(e) = listen(channel, ctx).await {
+            if let Err(e) = listen(replies.clone()).await {
                 debug!("Scanner: {:?}", e);
-                channel_2.send(Reply::ScannerStartFail).expect(ERROR);
+                replies.send(Reply::ScannerStartFail);
             }
         }
     })
 }
 
-async fn refresh_ui(ctx: egui::Context) {
-    let mut interval = interval(Duration::from_millis(200));
-    loop {
-        interval.tick().await;
-        ctx.request_repaint();
+async fn connect(connector: &dyn Connector, replies: &Replies) -> Option<Box<dyn Transport>> {
+    if connector.has_candidates() {
+        replies.send(Reply::Connecting);
     }
-}
-
-async fn read_line_timeout(handle: &mut BufReader<SerialStream>) -> Result<String> {
-    let mut buf = String::new();
-    match timeout(
-        Duration::from_millis(TIMEOUT_MS),
-        handle.read_line(&mut buf),
-    )
-    .await
-    {
+    match connector.connect().await {
+        Ok(handle) => {
+            replies.send(Reply::Connected(handle.name()));
+            Some(handle)
+        }
         Err(e) => {
-            debug!("Timeout: {:?}", e);
-            Err(e).with_context(|| "Connection timeout")
+            debug!("Connection error: {:?}", e);
+            replies.send(Reply::Disconnected);
+            None
         }
-        Ok(res) => {
-            res.map(|_| ()).map_err(anyhow::Error::from)?;
-            Ok(buf)
+    }
+}
+
+/// Told whenever the service has sent a reply, e.g. to wake up a UI.
+pub trait Notifier: Send + Sync {
+    fn notify(&self);
+}
+
+#[derive(Clone)]
+struct Replies {
+    channel: UnboundedSender<Reply>,
+    notifier: Option<Arc<dyn Notifier>>,
+}
+
+impl Replies {
+    /// False once nobody is listening.
+    fn send(&self, reply: Reply) -> bool {
+        let sent = self.channel.send(reply).is_ok();
+        if let Some(notifier) = &self.notifier {
+            notifier.notify();
         }
+        sent
     }
 }
 
-async fn check_connection(handle: &mut BufReader<SerialStream>) -> Result<bool> {
-    handle.write_all("connect\n".as_bytes()).await?;
-    let reply = read_line_timeout(handle).await?;
-    Ok(reply.trim() == "connected")
+/// Sends commands to a running service. The service stops once every
+/// handle is dropped.
+#[derive(Clone)]
+pub struct ServiceHandle {
+    commands: UnboundedSender<Command>,
 }
 
-async fn read_info(handle: &mut BufReader<SerialStream>) -> Result<String> {
-    handle.write_all("read\n".as_bytes()).await?;
-    let reply = read_line_timeout(handle).await?;
-    Ok(reply.trim().into())
+impl ServiceHandle {
+    pub fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|e| anyhow!("Service stopped, dropped {:?}", e.0))
+    }
 }
 
-#[tokio::main]
-pub async fn start_service(
-    mut receive_channel: tokio::sync::mpsc::UnboundedReceiver<Command>,
-    send_channel: tokio::sync::mpsc::UnboundedSender<Reply>,
-    ctx: egui::Context,
-) {
-    let mut scanner_task = start_listen_task(send_channel.clone(), ctx.clone());
-    tokio::spawn({
-        let ctx = ctx.clone();
-        async move { refresh_ui(ctx).await }
+/// Replies of a running service, in the order they were sent.
+pub struct Events {
+    replies: UnboundedReceiver<Reply>,
+}
+
+impl Events {
+    pub async fn next(&mut self) -> Option<Reply> {
+        self.replies.recv().await
+    }
+
+    pub fn try_next(&mut self) -> Option<Reply> {
+        self.replies.try_recv().ok()
+    }
+
+    /// Must not be called from async code.
+    pub fn blocking_next(&mut self) -> Option<Reply> {
+        self.replies.blocking_recv()
+    }
+}
+
+/// Starts the service on the current tokio runtime.
+pub fn spawn(
+    connector: Box<dyn Connector>,
+    notifier: Option<Arc<dyn Notifier>>,
+) -> (ServiceHandle, Events) {
+    let (handle, events, run) = service(connector, notifier);
+    tokio::spawn(run);
+    (handle, events)
+}
+
+/// Starts the service on a thread with its own runtime, for callers
+/// without one.
+pub fn spawn_thread(
+    connector: Box<dyn Connector>,
+    notifier: Option<Arc<dyn Notifier>>,
+) -> (ServiceHandle, Events) {
+    let (handle, events, run) = service(connector, notifier);
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to start runtime")
+            .block_on(run)
     });
-    let mut handle: Option<BufReader<SerialStream>> = None;
+    (handle, events)
+}
+
+fn service(
+    connector: Box<dyn Connector>,
+    notifier: Option<Arc<dyn Notifier>>,
+) -> (ServiceHandle, Events, impl std::future::Future<Output = ()>) {
+    let (command_send, command_receive) = unbounded_channel();
+    let (reply_send, reply_receive) = unbounded_channel();
+    let replies = Replies {
+        channel: reply_send,
+        notifier,
+    };
+    (
+        ServiceHandle {
+            commands: command_send,
+        },
+        Events {
+            replies: reply_receive,
+        },
+        run(command_receive, replies, connector),
+    )
+}
+
+async fn run(
+    mut receive_channel: UnboundedReceiver<Command>,
+    replies: Replies,
+    connector: Box<dyn Connector>,
+) {
+    let mut connector: Arc<dyn Connector> = connector.into();
+    let mut scanner_task = start_listen_task(replies.clone());
+    let mut handle: Option<Box<dyn Transport>> = None;
 
     loop {
         match receive_channel.recv().await {
             Some(Command::Connect) => {
                 debug!("Connection request");
-                if let Some(handle) = handle {
-                    send_channel
-                        .send(Reply::Connected(
-                            handle.get_ref().name().unwrap_or("Unknown port".into()),
-                        ))
-                        .expect(ERROR);
-                }
-                handle = match autoconnect(&send_channel).await {
-                    Ok(handle) => {
-                        send_channel
-                            .send(Reply::Connected(
-                                handle.get_ref().name().unwrap_or("Unknown port".into()),
-                            ))
-                            .expect(ERROR);
+                handle = match handle {
+                    Some(handle) => {
+                        replies.send(Reply::Connected(handle.name()));
                         Some(handle)
                     }
-                    Err(e) => {
-                        debug!("Connection error: {:?}", e);
-                        send_channel.send(Reply::Disconnected).expect(ERROR);
-                        None
-                    }
+                    None => connect(connector.as_ref(), &replies).await,
                 };
-                ctx.request_repaint();
             }
-            Some(Command::Read) => {
+            Some(Command::Read(id)) => {
                 handle = match handle {
                     None => {
-                        send_channel
-                            .send(Reply::ReadError("Not connected".into()))
-                            .expect(ERROR);
-                        send_channel.send(Reply::Disconnected).expect(ERROR);
+                        replies.send(Reply::ReadError(
+                            id,
+                            ReadStatus::NotConnected,
+                            "Not connected".into(),
+                        ));
+                        replies.send(Reply::Disconnected);
                         None
                     }
                     Some(mut handle) => {
-                        let result = read_info(&mut handle).await;
+                        let result = handle.read_info().await;
                         match result {
                             Err(e) => {
-                                send_channel
-                                    .send(Reply::ReadError(e.to_string()))
-                                    .expect(ERROR);
-                                send_channel.send(Reply::Disconnected).expect(ERROR);
+                                let status = if e.is::<transport::Timeout>() {
+                                    ReadStatus::Timeout
+                                } else {
+                                    ReadStatus::NotConnected
+                                };
+                                replies.send(Reply::ReadError(id, status, e.to_string()));
+                                replies.send(Reply::Disconnected);
                                 None
                             }
                             Ok(s) => {
-                                send_channel.send(Reply::Read(s)).expect(ERROR);
+                                // The device answered, so it stays connected
+                                // even if the answer makes no sense.
+                                let reply = match DeviceInfo::parse(&s) {
+                                    Ok(info) => Reply::Read(id, info),
+                                    Err(e) => {
+                                        warn!("Malformed reply {:?}: {:#}", s, e);
+                                        Reply::ReadError(
+                                            id,
+                                            ReadStatus::ProtocolError,
+                                            format!("Malformed reply: {e:#}"),
+                                        )
+                                    }
+                                };
+                                replies.send(reply);
                                 Some(handle)
                             }
                         }
                     }
                 };
-                ctx.request_repaint();
             }
-            Some(Command::Download(path, barcode, device)) => {
+            Some(Command::Download(path, records, options)) => {
                 debug!("Download to {:?}", path);
-                let mut data = HEADERS.join(",");
-                data.push('\n');
-                let rows = barcode.len().max(device.len());
-                data.push_str(
-                    &(0..rows)
-                        .map(|i| {
-                            format!(
-                                "{},{}",
-                                barcode.get(i).unwrap_or(&"".to_string()),
-                                device.get(i).unwrap_or(&"".to_string())
-                            )
-                        })
-                        .join("\n"),
-                );
-                if let Err(e) = std::fs::write(path, data.as_bytes()) {
-                    send_channel
-                        .send(Reply::DownloadError(
-                            format!("Download failed: {:?}", e).into(),
-                        ))
-                        .expect(ERROR);
-                    ctx.request_repaint();
+                if let Err(e) = export::write(&path, &records, &options) {
+                    replies.send(Reply::DownloadError(format!("Download failed: {:?}", e)));
                 }
             }
             Some(Command::StopScanner) => {
@@ -288,18 +301,17 @@ pub async fn start_service(
             Some(Command::StartScanner) => {
                 debug!("Start scanner command received");
                 scanner_task.abort();
-                scanner_task = start_listen_task(send_channel.clone(), ctx.clone());
+                scanner_task = start_listen_task(replies.clone());
             }
             Some(Command::CheckConnection) => {
                 debug!("Checking connection");
                 handle = {
                     if let Some(mut handle) = handle {
-                        if let Ok(true) = check_connection(&mut handle).await {
+                        if let Ok(true) = handle.health_check().await {
                             Some(handle)
                         } else {
                             debug!("Connection lost");
-                            send_channel.send(Reply::Disconnected).expect(ERROR);
-                            ctx.request_repaint();
+                            replies.send(Reply::Disconnected);
                             None
                         }
                     } else {
@@ -307,7 +319,153 @@ pub async fn start_service(
                     }
                 };
             }
+            Some(Command::ApplySettings(settings)) => {
+                debug!("Applying device settings: {:?}", settings);
+                connector = transport::from_settings(&settings).into();
+                if handle.take().is_some() {
+                    replies.send(Reply::Disconnected);
+                }
+            }
+            Some(Command::ListPorts) => {
+                debug!("Listing ports");
+                // Probes take seconds, so reads go on meanwhile. A probe
+                // waits for a connection attempt on the same port.
+                let connected = handle.as_ref().map(|h| h.name());
+                tokio::spawn({
+                    let connector = connector.clone();
+                    let replies = replies.clone();
+                    async move {
+                        let ports = connector.list_ports(connected).await;
+                        replies.send(Reply::Ports(ports));
+                    }
+                });
+            }
             None => break,
         }
     }
+    scanner_task.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use super::*;
+    use crate::transport::MockConnector;
+
+    fn start(online: bool) -> (MockConnector, ServiceHandle, Events) {
+        let mock = MockConnector::default();
+        mock.online.store(online, Ordering::Relaxed);
+        let (handle, events) = spawn(Box::new(mock.clone()), None);
+        (mock, handle, events)
+    }
+
+    /// The next reply about the device, whatever the scanner is doing.
+    async fn next(events: &mut Events) -> Reply {
+        loop {
+            match events.next().await.expect("service stopped") {
+                Reply::ScannerStartFail | Reply::BarcodeOutput(..) => continue,
+                reply => return reply,
+            }
+        }
+    }
+
+    async fn connect(handle: &ServiceHandle, events: &mut Events) {
+        handle.send(Command::Connect).unwrap();
+        assert!(matches!(next(events).await, Reply::Connecting));
+        assert!(matches!(next(events).await, Reply::Connected(name) if name == "mock"));
+    }
+
+    /// The next reply, if one comes within `ms`.
+    async fn within(events: &mut Events, ms: u64) -> Option<Reply> {
+        tokio::time::timeout(Duration::from_millis(ms), events.next())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn scanner_exit_stops_listening() {
+        // Prints its arguments as one barcode, then exits.
+        std::env::set_var(SCANNER_ENV, "echo");
+        let (_handle, mut events) = spawn(Box::new(MockConnector::default()), None);
+        assert!(matches!(
+            within(&mut events, 5000).await,
+            Some(Reply::BarcodeOutput(b, _)) if b.starts_with("--parent")
+        ));
+        assert!(matches!(
+            within(&mut events, 5000).await,
+            Some(Reply::ScannerStartFail)
+        ));
+        assert!(within(&mut events, 200).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn connects() {
+        let (_mock, handle, mut events) = start(true);
+        connect(&handle, &mut events).await;
+        handle.send(Command::Connect).unwrap();
+        assert!(matches!(next(&mut events).await, Reply::Connected(_)));
+    }
+
+    #[tokio::test]
+    async fn offline_device_is_disconnected() {
+        let (_mock, handle, mut events) = start(false);
+        handle.send(Command::Connect).unwrap();
+        assert!(matches!(next(&mut events).await, Reply::Connecting));
+        assert!(matches!(next(&mut events).await, Reply::Disconnected));
+    }
+
+    #[tokio::test]
+    async fn reads() {
+        let (mock, handle, mut events) = start(true);
+        mock.replies
+            .lock()
+            .unwrap()
+            .push_back(Ok("0x1F,31,2023-05-04".into()));
+        connect(&handle, &mut events).await;
+        handle.send(Command::Read(7)).unwrap();
+        match next(&mut events).await {
+            Reply::Read(7, info) => {
+                assert_eq!(info.serial_hex, "0x1F");
+                assert_eq!(info.serial_dec, 31);
+                assert_eq!(info.manufacture_date.to_string(), "2023-05-04");
+            }
+            reply => panic!("Unexpected {reply:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_timeout_disconnects() {
+        let (_mock, handle, mut events) = start(true);
+        connect(&handle, &mut events).await;
+        handle.send(Command::Read(1)).unwrap();
+        assert!(matches!(
+            next(&mut events).await,
+            Reply::ReadError(1, ReadStatus::Timeout, _)
+        ));
+        assert!(matches!(next(&mut events).await, Reply::Disconnected));
+        handle.send(Command::Read(2)).unwrap();
+        assert!(matches!(
+            next(&mut events).await,
+            Reply::ReadError(2, ReadStatus::NotConnected, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn malformed_reply_stays_connected() {
+        let (mock, handle, mut events) = start(true);
+        mock.replies.lock().unwrap().extend([
+            Ok("garbage".to_string()),
+            Ok("0x10,16,2023-01-01".to_string()),
+        ]);
+        connect(&handle, &mut events).await;
+        handle.send(Command::Read(1)).unwrap();
+        assert!(matches!(
+            next(&mut events).await,
+            Reply::ReadError(1, ReadStatus::ProtocolError, _)
+        ));
+        handle.send(Command::Read(2)).unwrap();
+        assert!(matches!(next(&mut events).await, Reply::Read(2, _)));
+    }
 }
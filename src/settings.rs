@@ -0,0 +1,337 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+#[cfg(feature = "gui")]
+use egui::*;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::ApiSettings, auto_export::AutoExport, duplicates::DuplicateSettings, export::CsvOptions,
+    mqtt::MqttSettings, validation::ValidationSettings, verification::VerificationSettings,
+    webhook::WebhookSettings,
+};
+
+const SETTINGS_DIR: &str = "sn-tracer";
+const SETTINGS_FILE: &str = "settings.toml";
+#[cfg(feature = "gui")]
+const BAUD_RATES: &[u32] = &[1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub station_id: String,
+    pub device: DeviceSettings,
+    pub validation: ValidationSettings,
+    pub verification: VerificationSettings,
+    pub duplicates: DuplicateSettings,
+    pub export: CsvOptions,
+    pub auto_export: AutoExport,
+    pub api: ApiSettings,
+    pub webhook: WebhookSettings,
+    pub mqtt: MqttSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        use sysinfo::SystemExt;
+        Self {
+            station_id: sysinfo::System::new().host_name().unwrap_or_default(),
+            device: DeviceSettings::default(),
+            validation: ValidationSettings::default(),
+            verification: VerificationSettings::default(),
+            duplicates: DuplicateSettings::default(),
+            export: CsvOptions::default(),
+            auto_export: AutoExport::default(),
+            api: ApiSettings::default(),
+            webhook: WebhookSettings::default(),
+            mqtt: MqttSettings::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DeviceSettings {
+    pub usb_filters: Vec<UsbFilter>,
+    /// USB serial number prefixes, empty accepts any.
+    pub serial_number_filters: Vec<String>,
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    /// Tried before autoconnect when set.
+    pub pinned_port: Option<String>,
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        Self {
+            usb_filters: vec![
+                UsbFilter {
+                    vid: None,
+                    pid: 24577,
+                },
+                UsbFilter {
+                    vid: None,
+                    pid: 29987,
+                },
+            ],
+            serial_number_filters: Vec::new(),
+            baud_rate: 9600,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            pinned_port: None,
+        }
+    }
+}
+
+impl DeviceSettings {
+    pub fn matches(&self, info: &tokio_serial::UsbPortInfo) -> bool {
+        let usb = self
+            .usb_filters
+            .iter()
+            .any(|f| f.pid == info.pid && f.vid.is_none_or(|vid| vid == info.vid));
+        let serial = self.serial_number_filters.is_empty()
+            || info.serial_number.as_ref().is_some_and(|sn| {
+                self.serial_number_filters
+                    .iter()
+                    .any(|f| sn.starts_with(f.as_str()))
+            });
+        usb && serial
+    }
+
+    pub fn builder(&self, port: &str) -> tokio_serial::SerialPortBuilder {
+        tokio_serial::new(port, self.baud_rate)
+            .parity(self.parity.into())
+            .stop_bits(self.stop_bits.into())
+            .flow_control(self.flow_control.into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsbFilter {
+    /// Any vendor when unset.
+    pub vid: Option<u16>,
+    pub pid: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+impl From<Parity> for tokio_serial::Parity {
+    fn from(value: Parity) -> Self {
+        match value {
+            Parity::None => Self::None,
+            Parity::Odd => Self::Odd,
+            Parity::Even => Self::Even,
+        }
+    }
+}
+
+impl From<StopBits> for tokio_serial::StopBits {
+    fn from(value: StopBits) -> Self {
+        match value {
+            StopBits::One => Self::One,
+            StopBits::Two => Self::Two,
+        }
+    }
+}
+
+impl From<FlowControl> for tokio_serial::FlowControl {
+    fn from(value: FlowControl) -> Self {
+        match value {
+            FlowControl::None => Self::None,
+            FlowControl::Software => Self::Software,
+            FlowControl::Hardware => Self::Hardware,
+        }
+    }
+}
+
+fn settings_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("No config directory")?
+        .join(SETTINGS_DIR)
+        .join(SETTINGS_FILE))
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let load = || -> Result<Self> {
+            let path = settings_path()?;
+            if !path.exists() {
+                return Ok(Self::default());
+            }
+            Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+        };
+        load().unwrap_or_else(|e| {
+            error!("Failed to load settings: {:?}", e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = settings_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {:?}", path))
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        CollapsingHeader::new("Station")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Station ID");
+                    ui.text_edit_singleline(&mut self.station_id);
+                });
+            });
+        CollapsingHeader::new("Device")
+            .default_open(true)
+            .show(ui, |ui| self.device.ui(ui));
+        CollapsingHeader::new("Validation")
+            .default_open(false)
+            .show(ui, |ui| self.validation.ui(ui));
+        CollapsingHeader::new("Verification")
+            .default_open(false)
+            .show(ui, |ui| self.verification.ui(ui));
+        CollapsingHeader::new("Duplicates")
+            .default_open(false)
+            .show(ui, |ui| self.duplicates.ui(ui));
+        CollapsingHeader::new("Export")
+            .default_open(false)
+            .show(ui, |ui| self.export.ui(ui));
+        CollapsingHeader::new("Auto-export")
+            .default_open(false)
+            .show(ui, |ui| self.auto_export.ui(ui));
+        CollapsingHeader::new("HTTP API")
+            .default_open(false)
+            .show(ui, |ui| self.api.ui(ui));
+        CollapsingHeader::new("Webhook")
+            .default_open(false)
+            .show(ui, |ui| self.webhook.ui(ui));
+        CollapsingHeader::new("MQTT")
+            .default_open(false)
+            .show(ui, |ui| self.mqtt.ui(ui));
+    }
+}
+
+impl DeviceSettings {
+    #[cfg(feature = "gui")]
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.label(RichText::new("Allowed USB devices").strong());
+        let mut remove = None;
+        for (i, filter) in self.usb_filters.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let mut any_vid = filter.vid.is_none();
+                if ui.checkbox(&mut any_vid, "Any VID").changed() {
+                    filter.vid = if any_vid { None } else { Some(0) };
+                }
+                if let Some(vid) = &mut filter.vid {
+                    ui.label("VID");
+                    ui.add(DragValue::new(vid).hexadecimal(4, false, true));
+                }
+                ui.label("PID");
+                ui.add(DragValue::new(&mut filter.pid).hexadecimal(4, false, true));
+                if ui.button("🗑").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.usb_filters.remove(i);
+        }
+        if ui.button("Add USB device").clicked() {
+            self.usb_filters.push(UsbFilter { vid: None, pid: 0 });
+        }
+        ui.separator();
+
+        ui.label(RichText::new("USB serial number prefixes").strong());
+        let mut remove = None;
+        for (i, filter) in self.serial_number_filters.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(filter);
+                if ui.button("🗑").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.serial_number_filters.remove(i);
+        }
+        if ui.button("Add prefix").clicked() {
+            self.serial_number_filters.push(String::new());
+        }
+        ui.separator();
+
+        Grid::new("serial_settings").num_columns(2).show(ui, |ui| {
+            ui.label("Baud rate");
+            ui.horizontal(|ui| {
+                ComboBox::from_id_source("baud_rate")
+                    .selected_text(self.baud_rate.to_string())
+                    .show_ui(ui, |ui| {
+                        for &rate in BAUD_RATES {
+                            ui.selectable_value(&mut self.baud_rate, rate, rate.to_string());
+                        }
+                    });
+                ui.add(DragValue::new(&mut self.baud_rate).clamp_range(1..=4_000_000));
+            });
+            ui.end_row();
+
+            ui.label("Parity");
+            ComboBox::from_id_source("parity")
+                .selected_text(format!("{:?}", self.parity))
+                .show_ui(ui, |ui| {
+                    for p in [Parity::None, Parity::Odd, Parity::Even] {
+                        ui.selectable_value(&mut self.parity, p, format!("{:?}", p));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Stop bits");
+            ComboBox::from_id_source("stop_bits")
+                .selected_text(format!("{:?}", self.stop_bits))
+                .show_ui(ui, |ui| {
+                    for s in [StopBits::One, StopBits::Two] {
+                        ui.selectable_value(&mut self.stop_bits, s, format!("{:?}", s));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Flow control");
+            ComboBox::from_id_source("flow_control")
+                .selected_text(format!("{:?}", self.flow_control))
+                .show_ui(ui, |ui| {
+                    for f in [
+                        FlowControl::None,
+                        FlowControl::Software,
+                        FlowControl::Hardware,
+                    ] {
+                        ui.selectable_value(&mut self.flow_control, f, format!("{:?}", f));
+                    }
+                });
+            ui.end_row();
+        });
+    }
+}
@@ -0,0 +1,511 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::record::{Field, ReadStatus, Record};
+
+const DATA_DIR: &str = "sn-tracer";
+const DB_FILE: &str = "records.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    station TEXT NOT NULL,
+    operator TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES sessions(id),
+    barcode TEXT NOT NULL,
+    serial_hex TEXT,
+    status TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS records_session ON records(session_id);
+CREATE INDEX IF NOT EXISTS records_barcode ON records(barcode);
+CREATE INDEX IF NOT EXISTS records_serial_hex ON records(serial_hex);
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY,
+    record_id INTEGER NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt INTEGER NOT NULL,
+    last_error TEXT,
+    failed INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS audit (
+    id INTEGER PRIMARY KEY,
+    record_id INTEGER NOT NULL,
+    session_id INTEGER NOT NULL,
+    at TEXT NOT NULL,
+    operator TEXT NOT NULL,
+    field TEXT NOT NULL,
+    old TEXT NOT NULL,
+    new TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS audit_record ON audit(record_id);
+";
+
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub id: u64,
+    pub started_at: DateTime<Local>,
+    pub station: String,
+    pub operator: String,
+    pub records: usize,
+    pub ok: usize,
+    /// Records whose label didn't match the device serial.
+    pub mismatches: usize,
+}
+
+/// A change an operator made to a record by hand. Deletions have the field
+/// `Deleted` and the barcode as the old value.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub record_id: u64,
+    pub session_id: u64,
+    pub at: DateTime<Local>,
+    pub operator: String,
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl AuditEntry {
+    /// Made now by `operator`, or by the logged in user when no operator
+    /// has been entered, so every change has someone behind it.
+    pub fn new(record: &Record, operator: &str, field: &str, old: String, new: String) -> Self {
+        let operator = match operator.trim() {
+            "" => std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_else(|_| "unknown".into()),
+            operator => operator.to_string(),
+        };
+        Self {
+            record_id: record.id,
+            session_id: record.session_id,
+            at: Local::now(),
+            operator,
+            field: field.to_string(),
+            old,
+            new,
+        }
+    }
+
+    /// One entry per field that differs between the two.
+    pub fn changes(before: &Record, after: &Record, operator: &str) -> Vec<Self> {
+        Field::ALL
+            .into_iter()
+            .map(|field| (field, before.field(field), after.field(field)))
+            .filter(|(_, old, new)| old != new)
+            .map(|(field, old, new)| Self::new(after, operator, field.header(), old, new))
+            .collect()
+    }
+}
+
+/// A record waiting to be posted to the webhook.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub id: u64,
+    pub record_id: u64,
+    pub payload: String,
+    pub attempts: u32,
+}
+
+/// Local record database. Records are kept whole as JSON next to the
+/// columns needed for lookups.
+pub struct Store {
+    conn: Connection,
+}
+
+pub fn default_path() -> Result<PathBuf> {
+    Ok(dirs::data_dir()
+        .context("No data directory")?
+        .join(DATA_DIR)
+        .join(DB_FILE))
+}
+
+impl Store {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open database {:?}", path))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    pub fn new_session(&self, station: &str, operator: &str) -> Result<u64> {
+        self.conn.execute(
+            "INSERT INTO sessions (started_at, station, operator) VALUES (?1, ?2, ?3)",
+            params![Local::now().to_rfc3339(), station, operator],
+        )?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    pub fn session_exists(&self, id: u64) -> Result<bool> {
+        Ok(self
+            .conn
+            .query_row("SELECT 1 FROM sessions WHERE id = ?1", [id], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    pub fn sessions(&self) -> Result<Vec<SessionSummary>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id, s.started_at, s.station, s.operator,
+                    COUNT(r.id), COALESCE(SUM(r.status = ?1), 0),
+                    COALESCE(SUM(json_extract(r.data, '$.verification') = 'Fail'), 0)
+             FROM sessions s LEFT JOIN records r ON r.session_id = s.id
+             GROUP BY s.id ORDER BY s.id DESC",
+        )?;
+        let rows = stmt.query_map([status_key(ReadStatus::Ok)], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, usize>(4)?,
+                row.get::<_, usize>(5)?,
+                row.get::<_, usize>(6)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (id, started_at, station, operator, records, ok, mismatches) = row?;
+            Ok(SessionSummary {
+                id,
+                started_at: DateTime::parse_from_rfc3339(&started_at)?.with_timezone(&Local),
+                station,
+                operator,
+                records,
+                ok,
+                mismatches,
+            })
+        })
+        .collect()
+    }
+
+    pub fn records(&self, session_id: u64) -> Result<Vec<Record>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, data FROM records WHERE session_id = ?1 ORDER BY id")?;
+        let rows = stmt.query_map([session_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.map(|row| {
+            let (id, data): (u64, String) = row?;
+            from_row(id, &data)
+        })
+        .collect()
+    }
+
+    pub fn record(&self, id: u64) -> Result<Option<Record>> {
+        self.conn
+            .query_row("SELECT data FROM records WHERE id = ?1", [id], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()?
+            .map(|data| from_row(id, &data))
+            .transpose()
+    }
+
+    /// Oldest record other than `except` with this barcode.
+    pub fn record_by_barcode(&self, barcode: &str, except: u64) -> Result<Option<Record>> {
+        self.first_record("barcode", barcode, except)
+    }
+
+    /// Oldest record other than `except` the device gave this serial for.
+    pub fn record_by_serial(&self, serial_hex: &str, except: u64) -> Result<Option<Record>> {
+        self.first_record("serial_hex", serial_hex, except)
+    }
+
+    fn first_record(&self, column: &str, value: &str, except: u64) -> Result<Option<Record>> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT id, data FROM records WHERE {column} = ?1 AND id != ?2
+                     ORDER BY id LIMIT 1"
+                ),
+                params![value, except],
+                |row| Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?
+            .map(|(id, data)| from_row(id, &data))
+            .transpose()
+    }
+
+    /// Stores a new record, assigning its id. Deleted records keep theirs in
+    /// the audit trail, so it isn't handed out again and undo can restore
+    /// them.
+    pub fn insert(&self, record: &mut Record) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO records (id, session_id, barcode, serial_hex, status, data)
+             VALUES (
+                 MAX(COALESCE((SELECT MAX(id) FROM records), 0),
+                     COALESCE((SELECT MAX(record_id) FROM audit), 0)) + 1,
+                 ?1, ?2, ?3, ?4, ?5
+             )",
+            params![
+                record.session_id,
+                record.barcode,
+                record.serial_hex,
+                status_key(record.status),
+                serde_json::to_string(record)?
+            ],
+        )?;
+        record.id = self.conn.last_insert_rowid() as u64;
+        Ok(())
+    }
+
+    /// Inserts or replaces a record keeping its id.
+    pub fn upsert(&self, record: &Record) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO records (id, session_id, barcode, serial_hex, status, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                record.id,
+                record.session_id,
+                record.barcode,
+                record.serial_hex,
+                status_key(record.status),
+                serde_json::to_string(record)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete(&self, id: u64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM records WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    pub fn audit(&self, entry: &AuditEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO audit (record_id, session_id, at, operator, field, old, new)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                entry.record_id,
+                entry.session_id,
+                entry.at.to_rfc3339(),
+                entry.operator,
+                entry.field,
+                entry.old,
+                entry.new
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Latest changes first, of one record or of all.
+    pub fn audit_trail(&self, record_id: Option<u64>, limit: usize) -> Result<Vec<AuditEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT record_id, session_id, at, operator, field, old, new FROM audit
+             WHERE ?1 IS NULL OR record_id = ?1 ORDER BY id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![record_id, limit], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, u64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, String>(6)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (record_id, session_id, at, operator, field, old, new) = row?;
+            Ok(AuditEntry {
+                record_id,
+                session_id,
+                at: DateTime::parse_from_rfc3339(&at)?.with_timezone(&Local),
+                operator,
+                field,
+                old,
+                new,
+            })
+        })
+        .collect()
+    }
+
+    pub fn enqueue_delivery(&self, record: &Record) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO outbox (record_id, payload, next_attempt) VALUES (?1, ?2, ?3)",
+            params![
+                record.id,
+                serde_json::to_string(record)?,
+                Local::now().timestamp_millis()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Deliveries not given up on whose retry time has come, oldest first.
+    pub fn due_deliveries(&self, limit: usize) -> Result<Vec<Delivery>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, record_id, payload, attempts FROM outbox
+             WHERE failed = 0 AND next_attempt <= ?1 ORDER BY id LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![Local::now().timestamp_millis(), limit], |row| {
+            Ok(Delivery {
+                id: row.get(0)?,
+                record_id: row.get(1)?,
+                payload: row.get(2)?,
+                attempts: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn delivered(&self, id: u64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM outbox WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// Records a failed attempt, retried at `retry_at` or given up on if none.
+    pub fn delivery_failed(
+        &self,
+        id: u64,
+        error: &str,
+        retry_at: Option<DateTime<Local>>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE outbox SET attempts = attempts + 1, last_error = ?2,
+                 next_attempt = COALESCE(?3, next_attempt), failed = ?3 IS NULL
+             WHERE id = ?1",
+            params![id, error, retry_at.map(|t| t.timestamp_millis())],
+        )?;
+        Ok(())
+    }
+
+    pub fn retry_failed_deliveries(&self) -> Result<()> {
+        self.conn.execute(
+            "UPDATE outbox SET failed = 0, next_attempt = ?1 WHERE failed = 1",
+            [Local::now().timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    /// Pending and failed deliveries.
+    pub fn delivery_counts(&self) -> Result<(usize, usize)> {
+        Ok(self.conn.query_row(
+            "SELECT COALESCE(SUM(failed = 0), 0), COALESCE(SUM(failed = 1), 0) FROM outbox",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?)
+    }
+
+    pub fn update(&self, record: &Record) -> Result<()> {
+        self.conn.execute(
+            "UPDATE records SET barcode = ?2, serial_hex = ?3, status = ?4, data = ?5
+             WHERE id = ?1",
+            params![
+                record.id,
+                record.barcode,
+                record.serial_hex,
+                status_key(record.status),
+                serde_json::to_string(record)?
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// The row id is authoritative, the JSON copy is written before it is known.
+fn from_row(id: u64, data: &str) -> Result<Record> {
+    let mut record: Record = serde_json::from_str(data)?;
+    record.id = id;
+    Ok(record)
+}
+
+fn status_key(status: ReadStatus) -> String {
+    format!("{:?}", status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_with_record() -> (Store, Record) {
+        let store = Store::open_in_memory().unwrap();
+        let session = store.new_session("line 1", "ann").unwrap();
+        let mut record = Record::new(
+            0,
+            session,
+            "SN-1".into(),
+            Local::now(),
+            "".into(),
+            "".into(),
+        );
+        store.insert(&mut record).unwrap();
+        (store, record)
+    }
+
+    #[test]
+    fn edit_is_audited_field_by_field() {
+        let (store, before) = store_with_record();
+        let mut after = before.clone();
+        after.set_barcode("SN-2".into());
+        after.note = Some("relabelled".into());
+        store.update(&after).unwrap();
+        for entry in AuditEntry::changes(&before, &after, "ann") {
+            store.audit(&entry).unwrap();
+        }
+
+        let trail = store.audit_trail(Some(before.id), 10).unwrap();
+        let mut changes: Vec<_> = trail
+            .iter()
+            .map(|e| (e.field.as_str(), e.old.as_str(), e.new.as_str()))
+            .collect();
+        changes.sort();
+        assert_eq!(
+            changes,
+            [
+                (Field::Barcode.header(), "SN-1", "SN-2"),
+                (Field::Note.header(), "", "relabelled"),
+            ]
+        );
+        assert!(trail.iter().all(|e| e.operator == "ann"));
+        assert!(AuditEntry::changes(&after, &after, "ann").is_empty());
+    }
+
+    #[test]
+    fn deletion_is_audited_and_its_id_not_reused() {
+        let (store, record) = store_with_record();
+        let entry = AuditEntry::new(&record, "", "Deleted", record.barcode.clone(), "".into());
+        store.audit(&entry).unwrap();
+        store.delete(record.id).unwrap();
+
+        assert!(store.record(record.id).unwrap().is_none());
+        let trail = store.audit_trail(None, 10).unwrap();
+        assert_eq!(trail.len(), 1);
+        assert_eq!(trail[0].record_id, record.id);
+        assert_eq!(trail[0].field, "Deleted");
+        assert_eq!(trail[0].old, "SN-1");
+        assert!(!trail[0].operator.is_empty());
+
+        let mut next = Record::new(
+            0,
+            record.session_id,
+            "SN-3".into(),
+            Local::now(),
+            "".into(),
+            "".into(),
+        );
+        store.insert(&mut next).unwrap();
+        assert!(next.id > record.id);
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use itertools::Itertools;
+use log::*;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::{timeout, Duration},
+};
+use tokio_serial::{SerialPort, SerialStream};
+
+use crate::settings::DeviceSettings;
+
+const TIMEOUT_MS: u64 = 1000;
+const HANDSHAKE_ATTEMPTS: usize = 20;
+const PROBE_ATTEMPTS: usize = 3;
+const PORT_ENV: &str = "SN_TRACER_PORT";
+const TCP_SCHEME: &str = "tcp://";
+
+/// The device didn't answer in time.
+#[derive(Debug)]
+pub struct Timeout;
+
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Connection timeout")
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// An open link to a device.
+#[async_trait]
+pub trait Transport: Send {
+    fn name(&self) -> String;
+
+    /// Sends `connect` and checks for `connected`.
+    async fn handshake(&mut self) -> Result<bool>;
+
+    /// Sends `read` and returns the trimmed reply.
+    async fn read_info(&mut self) -> Result<String>;
+
+    async fn health_check(&mut self) -> Result<bool> {
+        self.handshake().await
+    }
+}
+
+/// Finds and opens a device.
+#[async_trait]
+pub trait Connector: Send + Sync {
+    /// Whether a connection attempt is worth reporting as "connecting".
+    fn has_candidates(&self) -> bool {
+        true
+    }
+
+    async fn connect(&self) -> Result<Box<dyn Transport>>;
+
+    /// Every port this connector can see. Ports autoconnect would try, or
+    /// the pinned one, are probed unless `connected`; others are left alone.
+    async fn list_ports(&self, _connected: Option<String>) -> Vec<PortInfo> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PortInfo {
+    pub name: String,
+    pub usb: Option<tokio_serial::UsbPortInfo>,
+    /// Whether autoconnect would try this port.
+    pub allowed: bool,
+    pub handshake: Handshake,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Handshake {
+    Connected,
+    Answered,
+    Failed(String),
+    /// Neither allowed nor pinned, so not written to.
+    NotProbed,
+}
+
+/// USB discovery with `settings`, overridden by `SN_TRACER_PORT`: `tcp://host:port`
+/// for a TCP fixture, or a port path to try alongside USB discovery.
+pub fn from_settings(settings: &DeviceSettings) -> Box<dyn Connector> {
+    match std::env::var(PORT_ENV).ok().filter(|p| !p.is_empty()) {
+        Some(addr) if addr.starts_with(TCP_SCHEME) => Box::new(TcpConnector {
+            addr: addr.trim_start_matches(TCP_SCHEME).into(),
+        }),
+        port => Box::new(SerialConnector {
+            settings: settings.clone(),
+            extra_ports: port.into_iter().collect(),
+        }),
+    }
+}
+
+/// A serial port as seen by everything in this process that opens one.
+#[derive(Default)]
+struct PortUse {
+    /// Held while the port is being connected or probed.
+    handshake: tokio::sync::Mutex<()>,
+    /// Whether a transport has the port open.
+    open: AtomicBool,
+}
+
+fn port_use(port: &str) -> Arc<PortUse> {
+    static PORTS: OnceLock<Mutex<HashMap<String, Arc<PortUse>>>> = OnceLock::new();
+    PORTS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(port.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Marks a port open for as long as its transport lives.
+struct OpenPort(Arc<PortUse>);
+
+impl Drop for OpenPort {
+    fn drop(&mut self) {
+        self.0.open.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The line protocol over any byte stream.
+pub struct LineTransport<S> {
+    name: String,
+    stream: BufReader<S>,
+    port: Option<OpenPort>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> LineTransport<S> {
+    pub fn new(name: impl Into<String>, stream: S) -> Self {
+        Self {
+            name: name.into(),
+            stream: BufReader::new(stream),
+            port: None,
+        }
+    }
+
+    async fn read_line_timeout(&mut self) -> Result<String> {
+        let mut buf = String::new();
+        match timeout(
+            Duration::from_millis(TIMEOUT_MS),
+            self.stream.read_line(&mut buf),
+        )
+        .await
+        {
+            Err(e) => {
+                debug!("Timeout: {:?}", e);
+                Err(Timeout.into())
+            }
+            Ok(res) => {
+                if res? == 0 {
+                    bail!("Connection closed");
+                }
+                Ok(buf)
+            }
+        }
+    }
+
+    async fn request(&mut self, line: &str) -> Result<String> {
+        self.stream
+            .write_all(format!("{line}\n").as_bytes())
+            .await?;
+        Ok(self.read_line_timeout().await?.trim().into())
+    }
+
+    /// Retries the handshake until the device answers.
+    async fn establish(mut self, attempts: usize) -> Result<Self> {
+        for _ in 0..attempts {
+            if let Ok(true) = self.handshake().await {
+                debug!("Connected to {:?}", self.name);
+                return Ok(self);
+            }
+            tokio::time::sleep(Duration::from_millis(TIMEOUT_MS)).await;
+        }
+        bail!("Could not establish handshake with {:?}", self.name)
+    }
+}
+
+#[async_trait]
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Transport for LineTransport<S> {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn handshake(&mut self) -> Result<bool> {
+        Ok(self.request("connect").await? == "connected")
+    }
+
+    async fn read_info(&mut self) -> Result<String> {
+        self.request("read").await
+    }
+}
+
+/// USB serial discovery, racing every allowed port.
+pub struct SerialConnector {
+    pub settings: DeviceSettings,
+    pub extra_ports: Vec<String>,
+}
+
+impl SerialConnector {
+    fn allowed(&self, port: &tokio_serial::SerialPortInfo) -> bool {
+        if let tokio_serial::SerialPortType::UsbPort(ref info) = port.port_type {
+            debug!("Detected: {}, {:?}", port.port_name, info);
+            return self.settings.matches(info);
+        }
+        false
+    }
+
+    fn available_ports(&self) -> Vec<String> {
+        let devices = tokio_serial::available_ports().unwrap_or_default();
+        devices
+            .into_iter()
+            .filter(|d| self.allowed(d))
+            .map(|d| d.port_name)
+            .chain(self.extra_ports.iter().cloned())
+            .filter(|d| Some(d) != self.settings.pinned_port.as_ref())
+            .unique()
+            .collect()
+    }
+
+    /// Waits for any other handshake on the port, so probes and connection
+    /// attempts never talk over each other.
+    async fn try_connect(
+        builder: tokio_serial::SerialPortBuilder,
+        device: String,
+        attempts: usize,
+    ) -> Result<LineTransport<SerialStream>> {
+        let port = port_use(&device);
+        let _handshake = port.handshake.lock().await;
+        if port.open.load(Ordering::Relaxed) {
+            bail!("{device:?} is already open");
+        }
+        let handle = SerialStream::open(&builder)?;
+        debug!("Handle obtained: {:?}", handle);
+        let name = handle.name().unwrap_or(device);
+        let mut transport = LineTransport::new(name, handle).establish(attempts).await?;
+        port.open.store(true, Ordering::Relaxed);
+        transport.port = Some(OpenPort(port.clone()));
+        Ok(transport)
+    }
+
+    async fn probe(
+        builder: tokio_serial::SerialPortBuilder,
+        port: tokio_serial::SerialPortInfo,
+        allowed: bool,
+        connected: bool,
+        pinned: bool,
+    ) -> PortInfo {
+        let handshake = if connected {
+            Handshake::Connected
+        } else if !allowed && !pinned {
+            Handshake::NotProbed
+        } else {
+            let port_use = port_use(&port.port_name);
+            let result = Self::try_connect(builder, port.port_name.clone(), PROBE_ATTEMPTS).await;
+            match result {
+                Ok(_) => Handshake::Answered,
+                // Connected while this probe waited for its turn.
+                Err(_) if port_use.open.load(Ordering::Relaxed) => Handshake::Connected,
+                Err(e) => Handshake::Failed(e.to_string()),
+            }
+        };
+        PortInfo {
+            name: port.port_name,
+            usb: match port.port_type {
+                tokio_serial::SerialPortType::UsbPort(info) => Some(info),
+                _ => None,
+            },
+            allowed,
+            handshake,
+        }
+    }
+}
+
+#[async_trait]
+impl Connector for SerialConnector {
+    fn has_candidates(&self) -> bool {
+        self.settings.pinned_port.is_some() || !self.available_ports().is_empty()
+    }
+
+    async fn connect(&self) -> Result<Box<dyn Transport>> {
+        if let Some(pinned) = &self.settings.pinned_port {
+            // Only briefly, so a silent pinned device doesn't hold up the
+            // fallback and every command queued behind it.
+            match Self::try_connect(
+                self.settings.builder(pinned),
+                pinned.clone(),
+                PROBE_ATTEMPTS,
+            )
+            .await
+            {
+                Ok(t) => return Ok(Box::new(t)),
+                Err(e) => debug!("Pinned port {:?} failed, falling back: {:?}", pinned, e),
+            }
+        }
+        let mut futures = tokio::task::JoinSet::new();
+        self.available_ports().into_iter().for_each(|d| {
+            futures.spawn(Self::try_connect(
+                self.settings.builder(&d),
+                d,
+                HANDSHAKE_ATTEMPTS,
+            ));
+        });
+        debug!("Connection attempts: {}", futures.len());
+        let mut results = Vec::new();
+        while let Some(Ok(res)) = futures.join_next().await {
+            results.push(res);
+        }
+        results
+            .into_iter()
+            .inspect(|r| debug!("{:?}", r.as_ref().map(|t| &t.name)))
+            .find_map(Result::ok)
+            .map(|t| Box::new(t) as Box<dyn Transport>)
+            .context("Failed to connect to available devices")
+    }
+
+    async fn list_ports(&self, connected: Option<String>) -> Vec<PortInfo> {
+        let mut ports = tokio_serial::available_ports().unwrap_or_default();
+        for extra in &self.extra_ports {
+            if !ports.iter().any(|p| &p.port_name == extra) {
+                ports.push(tokio_serial::SerialPortInfo {
+                    port_name: extra.clone(),
+                    port_type: tokio_serial::SerialPortType::Unknown,
+                });
+            }
+        }
+        let mut probes = tokio::task::JoinSet::new();
+        for port in ports {
+            let allowed = self.allowed(&port) || self.extra_ports.contains(&port.port_name);
+            let builder = self.settings.builder(&port.port_name);
+            let connected = connected.as_ref() == Some(&port.port_name);
+            let pinned = self.settings.pinned_port.as_ref() == Some(&port.port_name);
+            probes.spawn(Self::probe(builder, port, allowed, connected, pinned));
+        }
+        let mut infos = Vec::new();
+        while let Some(Ok(info)) = probes.join_next().await {
+            infos.push(info);
+        }
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+}
+
+/// A fixture reachable over TCP, e.g. a serial-to-ethernet bridge.
+pub struct TcpConnector {
+    pub addr: String,
+}
+
+#[async_trait]
+impl Connector for TcpConnector {
+    async fn connect(&self) -> Result<Box<dyn Transport>> {
+        let stream = TcpStream::connect(&self.addr).await?;
+        let name = format!("{TCP_SCHEME}{}", self.addr);
+        Ok(Box::new(
+            LineTransport::new(name, stream)
+                .establish(HANDSHAKE_ATTEMPTS)
+                .await?,
+        ))
+    }
+}
+
+/// In-memory device replying from a shared queue of `read` replies.
+#[derive(Clone, Default)]
+pub struct MockConnector {
+    pub replies: Arc<Mutex<VecDeque<Result<String, String>>>>,
+    pub online: Arc<AtomicBool>,
+}
+
+pub struct MockTransport(MockConnector);
+
+impl MockConnector {
+    fn is_online(&self) -> bool {
+        self.online.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl Connector for MockConnector {
+    async fn connect(&self) -> Result<Box<dyn Transport>> {
+        if !self.is_online() {
+            bail!("Mock device offline");
+        }
+        Ok(Box::new(MockTransport(self.clone())))
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    fn name(&self) -> String {
+        "mock".into()
+    }
+
+    async fn handshake(&mut self) -> Result<bool> {
+        Ok(self.0.is_online())
+    }
+
+    async fn read_info(&mut self) -> Result<String> {
+        if !self.0.is_online() {
+            return Err(Timeout.into());
+        }
+        let reply = self.0.replies.lock().unwrap().pop_front();
+        match reply {
+            Some(Ok(reply)) => Ok(reply),
+            Some(Err(e)) => bail!(e),
+            None => Err(Timeout.into()),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+use crate::record::Record;
+
+/// Steps kept for undo, the oldest are dropped first.
+const LIMIT: usize = 100;
+
+/// A change an operator made to the records, with what it takes to revert it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Change {
+    Edit {
+        before: Box<Record>,
+        after: Box<Record>,
+    },
+    Delete(Box<Record>),
+    /// "Clear", which moves on to a new session.
+    NewSession {
+        from: u64,
+        to: u64,
+    },
+}
+
+impl Change {
+    pub fn edit(before: Record, after: Record) -> Self {
+        Change::Edit {
+            before: Box::new(before),
+            after: Box::new(after),
+        }
+    }
+
+    pub fn delete(record: Record) -> Self {
+        Change::Delete(Box::new(record))
+    }
+}
+
+/// Undo and redo stacks of steps, each step being the changes of one action.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct UndoStack {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+}
+
+impl UndoStack {
+    pub fn push(&mut self, step: Vec<Change>) {
+        if step.is_empty() {
+            return;
+        }
+        self.undo.push(step);
+        if self.undo.len() > LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// The step `undo` would revert.
+    pub fn next_undo(&self) -> Option<&[Change]> {
+        self.undo.last().map(Vec::as_slice)
+    }
+
+    /// The step `redo` would apply.
+    pub fn next_redo(&self) -> Option<&[Change]> {
+        self.redo.last().map(Vec::as_slice)
+    }
+
+    /// The step to revert, moved over to redo.
+    pub fn undo(&mut self) -> Option<Vec<Change>> {
+        let step = self.undo.pop()?;
+        self.redo.push(step.clone());
+        Some(step)
+    }
+
+    /// The step to apply again, moved back to undo.
+    pub fn redo(&mut self) -> Option<Vec<Change>> {
+        let step = self.redo.pop()?;
+        self.undo.push(step.clone());
+        Some(step)
+    }
+}
@@ -0,0 +1,312 @@
+#[cfg(feature = "gui")]
+use egui::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Code 39 characters in check value order.
+const MOD43_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    None,
+    /// GS1 check digit as on EAN, UPC and GTIN labels.
+    Mod10,
+    /// Code 39 check character.
+    Mod43,
+}
+
+/// Rules a barcode must pass before the device is read for it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ValidationSettings {
+    pub enabled: bool,
+    /// Must match the whole barcode. A `product` group is checked against
+    /// `products` instead of the whole barcode.
+    pub pattern: String,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    /// Any of these, empty accepts any.
+    pub prefixes: Vec<String>,
+    pub checksum: Checksum,
+    /// Allowed product codes, empty accepts any.
+    pub products: Vec<String>,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            pattern: String::new(),
+            min_length: None,
+            max_length: None,
+            prefixes: Vec::new(),
+            checksum: Checksum::None,
+            products: Vec::new(),
+        }
+    }
+}
+
+pub(crate) fn mod10(barcode: &str) -> bool {
+    let digits: Option<Vec<u32>> = barcode.chars().map(|c| c.to_digit(10)).collect();
+    let Some((check, data)) = digits.as_deref().and_then(|d| d.split_last()) else {
+        return false;
+    };
+    let sum: u32 = data
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+    !data.is_empty() && (10 - sum % 10) % 10 == *check
+}
+
+fn mod43(barcode: &str) -> bool {
+    let values: Option<Vec<usize>> = barcode.chars().map(|c| MOD43_CHARS.find(c)).collect();
+    match values.as_deref().and_then(|v| v.split_last()) {
+        Some((check, data)) if !data.is_empty() => data.iter().sum::<usize>() % 43 == *check,
+        _ => false,
+    }
+}
+
+impl ValidationSettings {
+    /// The reason `barcode` is rejected, if it is.
+    pub fn validate(&self, barcode: &str) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let length = barcode.chars().count();
+        if let Some(min) = self.min_length.filter(|&min| length < min) {
+            return Err(format!(
+                "Too short, {length} characters instead of at least {min}"
+            ));
+        }
+        if let Some(max) = self.max_length.filter(|&max| length > max) {
+            return Err(format!(
+                "Too long, {length} characters instead of at most {max}"
+            ));
+        }
+        if !self.prefixes.is_empty() && !self.prefixes.iter().any(|p| barcode.starts_with(p)) {
+            return Err(format!("Prefix not one of {}", self.prefixes.join(", ")));
+        }
+        let mut product = barcode;
+        if !self.pattern.is_empty() {
+            let regex = Regex::new(&format!("^(?:{})$", self.pattern))
+                .map_err(|e| format!("Invalid pattern: {e}"))?;
+            let captures = regex
+                .captures(barcode)
+                .ok_or_else(|| format!("Does not match {}", self.pattern))?;
+            if let Some(m) = captures.name("product") {
+                product = m.as_str();
+            }
+        }
+        let checksum_ok = match self.checksum {
+            Checksum::None => true,
+            Checksum::Mod10 => mod10(barcode),
+            Checksum::Mod43 => mod43(barcode),
+        };
+        if !checksum_ok {
+            return Err(format!("{:?} checksum failed", self.checksum));
+        }
+        if !self.products.is_empty() && !self.products.iter().any(|p| p == product) {
+            return Err(format!("Product {product} not allowed"));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "Reject scans that break these rules");
+        Grid::new("validation").num_columns(2).show(ui, |ui| {
+            ui.label("Pattern");
+            ui.vertical(|ui| {
+                ui.text_edit_singleline(&mut self.pattern);
+                if let Err(e) = Regex::new(&self.pattern) {
+                    ui.colored_label(Color32::RED, e.to_string());
+                }
+            });
+            ui.end_row();
+
+            ui.label("Length");
+            ui.horizontal(|ui| {
+                optional_length(ui, "Min", &mut self.min_length);
+                optional_length(ui, "Max", &mut self.max_length);
+            });
+            ui.end_row();
+
+            ui.label("Checksum");
+            ComboBox::from_id_source("checksum")
+                .selected_text(format!("{:?}", self.checksum))
+                .show_ui(ui, |ui| {
+                    for c in [Checksum::None, Checksum::Mod10, Checksum::Mod43] {
+                        ui.selectable_value(&mut self.checksum, c, format!("{:?}", c));
+                    }
+                });
+            ui.end_row();
+        });
+        ui.separator();
+
+        ui.label(RichText::new("Prefixes").strong());
+        string_list_ui(ui, &mut self.prefixes, "Add prefix");
+        ui.separator();
+
+        ui.label(RichText::new("Allowed products").strong());
+        string_list_ui(ui, &mut self.products, "Add product");
+    }
+}
+
+#[cfg(feature = "gui")]
+fn optional_length(ui: &mut Ui, label: &str, value: &mut Option<usize>) {
+    let mut enabled = value.is_some();
+    if ui.checkbox(&mut enabled, label).changed() {
+        *value = enabled.then_some(1);
+    }
+    if let Some(length) = value {
+        ui.add(DragValue::new(length).clamp_range(1..=1000));
+    }
+}
+
+#[cfg(feature = "gui")]
+fn string_list_ui(ui: &mut Ui, values: &mut Vec<String>, add: &str) {
+    let mut remove = None;
+    for (i, value) in values.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(value);
+            if ui.button("🗑").clicked() {
+                remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = remove {
+        values.remove(i);
+    }
+    if ui.button(add).clicked() {
+        values.push(String::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ValidationSettings {
+        ValidationSettings {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validates_length_prefix_pattern_and_checksum() {
+        let lengths = ValidationSettings {
+            min_length: Some(3),
+            max_length: Some(5),
+            ..settings()
+        };
+        let prefixes = ValidationSettings {
+            prefixes: vec!["SN".into(), "AB".into()],
+            ..settings()
+        };
+        let pattern = ValidationSettings {
+            pattern: "SN-[0-9]{4}".into(),
+            ..settings()
+        };
+        let mod10 = ValidationSettings {
+            checksum: Checksum::Mod10,
+            ..settings()
+        };
+        let mod43 = ValidationSettings {
+            checksum: Checksum::Mod43,
+            ..settings()
+        };
+        let disabled = ValidationSettings {
+            enabled: false,
+            ..lengths.clone()
+        };
+        let cases: &[(&ValidationSettings, &str, bool)] = &[
+            (&settings(), "anything", true),
+            (&disabled, "X", true),
+            (&lengths, "abc", true),
+            (&lengths, "abcde", true),
+            (&lengths, "ab", false),
+            (&lengths, "abcdef", false),
+            // Characters, not bytes.
+            (&lengths, "ééé", true),
+            (&prefixes, "SN-1", true),
+            (&prefixes, "AB1", true),
+            (&prefixes, "XSN-1", false),
+            (&pattern, "SN-1234", true),
+            // The whole barcode has to match.
+            (&pattern, "SN-12345", false),
+            (&pattern, "XSN-1234", false),
+            (&mod10, "4006381333931", true),
+            (&mod10, "4006381333932", false),
+            (&mod43, "CODE39W", true),
+            (&mod43, "CODE39X", false),
+        ];
+        for (settings, barcode, valid) in cases {
+            assert_eq!(
+                settings.validate(barcode).is_ok(),
+                *valid,
+                "{barcode:?} with {settings:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn selects_product_group() {
+        let products = ValidationSettings {
+            pattern: "(?P<product>[A-Z]{2})-[0-9]+".into(),
+            products: vec!["AB".into(), "CD".into()],
+            ..settings()
+        };
+        assert_eq!(products.validate("AB-1"), Ok(()));
+        assert_eq!(products.validate("CD-22"), Ok(()));
+        assert_eq!(
+            products.validate("EF-1"),
+            Err("Product EF not allowed".into())
+        );
+        assert!(products.validate("AB1").is_err());
+
+        // Without a pattern the whole barcode is the product.
+        let whole = ValidationSettings {
+            products: vec!["AB-1".into()],
+            ..settings()
+        };
+        assert_eq!(whole.validate("AB-1"), Ok(()));
+        assert!(whole.validate("AB-2").is_err());
+
+        let invalid = ValidationSettings {
+            pattern: "(".into(),
+            ..settings()
+        };
+        assert!(invalid
+            .validate("(")
+            .unwrap_err()
+            .starts_with("Invalid pattern"));
+    }
+
+    #[test]
+    fn mod10_checks_gs1_check_digits() {
+        for good in [
+            "09501101530003",
+            "4006381333931",
+            "73513537",
+            "00012345600012",
+        ] {
+            assert!(mod10(good), "{good}");
+        }
+        for bad in ["09501101530004", "4006381333930", "7", "", "0950110153000A"] {
+            assert!(!mod10(bad), "{bad}");
+        }
+    }
+
+    #[test]
+    fn mod43_checks_code39_check_characters() {
+        for good in ["CODE39W", "WIKIPEDIA$", "ABC-1234-"] {
+            assert!(mod43(good), "{good}");
+        }
+        for bad in ["CODE39X", "WIKIPEDIA", "W", "", "code39W"] {
+            assert!(!mod43(bad), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,198 @@
+#[cfg(feature = "gui")]
+use egui::*;
+use log::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::record::{Record, Verdict};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelPart {
+    Whole,
+    /// AI 21 of a GS1 barcode.
+    Gs1Serial,
+    /// The `serial` group of `pattern`, else its first group.
+    Pattern,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceSerial {
+    Hex,
+    Dec,
+}
+
+/// Compares the serial on the label with the one the device reports.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct VerificationSettings {
+    pub enabled: bool,
+    pub label_part: LabelPart,
+    pub pattern: String,
+    pub device_serial: DeviceSerial,
+    /// Run through the shell after each check, empty for silence.
+    pub pass_sound: String,
+    pub fail_sound: String,
+}
+
+#[cfg(target_os = "windows")]
+const SOUNDS: (&str, &str) = (
+    "powershell -c [System.Media.SystemSounds]::Asterisk.Play()",
+    "powershell -c [System.Media.SystemSounds]::Hand.Play()",
+);
+#[cfg(target_os = "macos")]
+const SOUNDS: (&str, &str) = (
+    "afplay /System/Library/Sounds/Glass.aiff",
+    "afplay /System/Library/Sounds/Basso.aiff",
+);
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const SOUNDS: (&str, &str) = (
+    "canberra-gtk-play -i complete",
+    "canberra-gtk-play -i dialog-error",
+);
+
+impl Default for VerificationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            label_part: LabelPart::Whole,
+            pattern: String::new(),
+            device_serial: DeviceSerial::Hex,
+            pass_sound: String::new(),
+            fail_sound: SOUNDS.1.into(),
+        }
+    }
+}
+
+/// Case, `0x` and leading zeros don't tell serials apart.
+fn normalize(serial: &str) -> String {
+    let serial = serial.trim().to_uppercase();
+    let serial = serial.strip_prefix("0X").unwrap_or(&serial);
+    match serial.trim_start_matches('0') {
+        "" if !serial.is_empty() => "0".into(),
+        s => s.into(),
+    }
+}
+
+impl VerificationSettings {
+    fn label_serial(&self, record: &Record) -> Option<String> {
+        match self.label_part {
+            LabelPart::Whole => Some(record.barcode.clone()),
+            LabelPart::Gs1Serial => record.gs1_serial.clone(),
+            LabelPart::Pattern => {
+                let regex = Regex::new(&self.pattern).ok()?;
+                let captures = regex.captures(&record.barcode)?;
+                captures
+                    .name("serial")
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0))
+                    .map(|m| m.as_str().to_string())
+            }
+        }
+    }
+
+    /// Verdict for a record that has its reading, if verifying.
+    pub fn check(&self, record: &Record) -> Option<Verdict> {
+        if !self.enabled {
+            return None;
+        }
+        let device = match self.device_serial {
+            DeviceSerial::Hex => record.serial_hex.as_deref(),
+            DeviceSerial::Dec => record.serial_dec.as_deref(),
+        }?;
+        let label = self.label_serial(record);
+        let pass = label.is_some_and(|label| normalize(&label) == normalize(device));
+        Some(if pass { Verdict::Pass } else { Verdict::Fail })
+    }
+
+    pub fn play(&self, verdict: Verdict) {
+        let command = match verdict {
+            Verdict::Pass => &self.pass_sound,
+            Verdict::Fail => &self.fail_sound,
+        };
+        if command.trim().is_empty() {
+            return;
+        }
+        #[cfg(target_os = "windows")]
+        let result = std::process::Command::new("cmd")
+            .args(["/C", command])
+            .spawn();
+        #[cfg(not(target_os = "windows"))]
+        let result = std::process::Command::new("sh")
+            .args(["-c", command])
+            .spawn();
+        match result {
+            // Waited for off the UI thread so it doesn't linger as a zombie.
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => warn!("Failed to play sound: {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(
+            &mut self.enabled,
+            "Compare the label with the device serial",
+        );
+        Grid::new("verification").num_columns(2).show(ui, |ui| {
+            ui.label("Label serial");
+            ComboBox::from_id_source("label_part")
+                .selected_text(label_part_name(self.label_part))
+                .show_ui(ui, |ui| {
+                    for p in [LabelPart::Whole, LabelPart::Gs1Serial, LabelPart::Pattern] {
+                        ui.selectable_value(&mut self.label_part, p, label_part_name(p));
+                    }
+                });
+            ui.end_row();
+
+            if self.label_part == LabelPart::Pattern {
+                ui.label("Pattern");
+                ui.vertical(|ui| {
+                    ui.text_edit_singleline(&mut self.pattern);
+                    if let Err(e) = Regex::new(&self.pattern) {
+                        ui.colored_label(Color32::RED, e.to_string());
+                    }
+                });
+                ui.end_row();
+            }
+
+            ui.label("Device serial");
+            ComboBox::from_id_source("device_serial")
+                .selected_text(format!("{:?}", self.device_serial))
+                .show_ui(ui, |ui| {
+                    for s in [DeviceSerial::Hex, DeviceSerial::Dec] {
+                        ui.selectable_value(&mut self.device_serial, s, format!("{:?}", s));
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Pass sound");
+            sound_ui(ui, &mut self.pass_sound, SOUNDS.0);
+            ui.end_row();
+
+            ui.label("Fail sound");
+            sound_ui(ui, &mut self.fail_sound, SOUNDS.1);
+            ui.end_row();
+        });
+    }
+}
+
+#[cfg(feature = "gui")]
+fn sound_ui(ui: &mut Ui, command: &mut String, default: &str) {
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(command);
+        if ui.button("Default").clicked() {
+            *command = default.into();
+        }
+    });
+}
+
+#[cfg(feature = "gui")]
+fn label_part_name(part: LabelPart) -> &'static str {
+    match part {
+        LabelPart::Whole => "Whole barcode",
+        LabelPart::Gs1Serial => "GS1 serial (21)",
+        LabelPart::Pattern => "Pattern",
+    }
+}
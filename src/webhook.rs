@@ -0,0 +1,353 @@
+#[cfg(feature = "webhook")]
+use std::{
+    collections::HashSet,
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "webhook")]
+use chrono::Local;
+#[cfg(feature = "gui")]
+use egui::*;
+#[cfg(feature = "webhook")]
+use log::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "webhook")]
+use crate::{
+    service::Notifier,
+    store::{Delivery, Store},
+};
+
+#[cfg(feature = "webhook")]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(feature = "webhook")]
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(feature = "webhook")]
+const MAX_BACKOFF_SECS: u64 = 300;
+#[cfg(feature = "webhook")]
+const BATCH: usize = 20;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct WebhookSettings {
+    pub enabled: bool,
+    pub url: String,
+    /// Sent as a bearer token when set.
+    pub token: Option<String>,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "http://localhost:8000/records".into(),
+            token: None,
+        }
+    }
+}
+
+impl WebhookSettings {
+    #[cfg(feature = "gui")]
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.enabled, "POST each record as JSON");
+        Grid::new("webhook").num_columns(2).show(ui, |ui| {
+            ui.label("URL");
+            ui.text_edit_singleline(&mut self.url);
+            ui.end_row();
+
+            ui.label("Bearer token");
+            let mut token = self.token.clone().unwrap_or_default();
+            if ui
+                .add(TextEdit::singleline(&mut token).password(true))
+                .changed()
+            {
+                self.token = Some(token).filter(|t| !t.is_empty());
+            }
+            ui.end_row();
+        });
+        ui.label("Records are kept in an outbox and retried until the server accepts them.");
+    }
+}
+
+#[cfg(feature = "webhook")]
+enum Outcome {
+    Delivered,
+    /// Unreachable, a server error or too busy, worth trying again.
+    Retry(String),
+    /// The server refused the record, retrying won't help.
+    Rejected(String),
+}
+
+#[cfg(feature = "webhook")]
+fn post(settings: &WebhookSettings, delivery: &Delivery) -> Outcome {
+    let mut request = ureq::post(&settings.url)
+        .timeout(REQUEST_TIMEOUT)
+        .set("Content-Type", "application/json");
+    if let Some(token) = &settings.token {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+    match request.send_string(&delivery.payload) {
+        Ok(_) => Outcome::Delivered,
+        // Request Timeout and Too Many Requests only mean "not now".
+        Err(ureq::Error::Status(code, response))
+            if (400..500).contains(&code) && !matches!(code, 408 | 429) =>
+        {
+            Outcome::Rejected(format!("HTTP {code} {}", response.status_text()))
+        }
+        Err(ureq::Error::Status(code, response)) => {
+            Outcome::Retry(format!("HTTP {code} {}", response.status_text()))
+        }
+        Err(e) => Outcome::Retry(e.to_string()),
+    }
+}
+
+#[cfg(feature = "webhook")]
+/// Exponential backoff between attempts, capped at five minutes.
+fn backoff(attempts: u32) -> chrono::Duration {
+    chrono::Duration::seconds(2u64.saturating_pow(attempts).min(MAX_BACKOFF_SECS) as i64)
+}
+
+#[cfg(feature = "webhook")]
+/// Posts outbox entries on a worker thread. The outbox itself stays in the
+/// app's store, so nothing is lost if the app closes mid-delivery.
+pub struct Webhook {
+    jobs: mpsc::Sender<(WebhookSettings, Delivery)>,
+    outcomes: mpsc::Receiver<(Delivery, Outcome)>,
+    in_flight: HashSet<u64>,
+    last_poll: Option<Instant>,
+    /// Pending and failed deliveries.
+    pub counts: (usize, usize),
+}
+
+#[cfg(feature = "webhook")]
+impl Webhook {
+    pub fn start(notifier: Option<Arc<dyn Notifier>>) -> Self {
+        let (jobs, job_receive) = mpsc::channel::<(WebhookSettings, Delivery)>();
+        let (outcome_send, outcomes) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (settings, delivery) in job_receive {
+                debug!("Posting record {} to {}", delivery.record_id, settings.url);
+                let outcome = post(&settings, &delivery);
+                if outcome_send.send((delivery, outcome)).is_err() {
+                    break;
+                }
+                if let Some(notifier) = &notifier {
+                    notifier.notify();
+                }
+            }
+        });
+        Self {
+            jobs,
+            outcomes,
+            in_flight: HashSet::new(),
+            last_poll: None,
+            counts: (0, 0),
+        }
+    }
+
+    /// Applies finished deliveries and hands due ones to the worker.
+    pub fn update(&mut self, store: &Store, settings: &WebhookSettings) -> anyhow::Result<()> {
+        let mut changed = false;
+        while let Ok((delivery, outcome)) = self.outcomes.try_recv() {
+            self.in_flight.remove(&delivery.id);
+            changed = true;
+            match outcome {
+                Outcome::Delivered => store.delivered(delivery.id)?,
+                Outcome::Retry(e) => {
+                    warn!("Delivery of record {} failed: {}", delivery.record_id, e);
+                    let retry_at = Local::now() + backoff(delivery.attempts);
+                    store.delivery_failed(delivery.id, &e, Some(retry_at))?;
+                }
+                Outcome::Rejected(e) => {
+                    error!("Record {} rejected: {}", delivery.record_id, e);
+                    store.delivery_failed(delivery.id, &e, None)?;
+                }
+            }
+        }
+        let due = self.last_poll.is_none_or(|t| t.elapsed() > POLL_INTERVAL);
+        if settings.enabled && due {
+            self.last_poll = Some(Instant::now());
+            for delivery in store.due_deliveries(BATCH)? {
+                if self.in_flight.insert(delivery.id) {
+                    let _ = self.jobs.send((settings.clone(), delivery));
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            self.counts = store.delivery_counts()?;
+        }
+        Ok(())
+    }
+
+    pub fn retry_failed(&mut self, store: &Store) -> anyhow::Result<()> {
+        store.retry_failed_deliveries()?;
+        self.last_poll = None;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "webhook"))]
+mod tests {
+    use chrono::Local;
+
+    use super::*;
+    use crate::record::Record;
+
+    /// Answers one request per status and hands back what was posted.
+    fn serve(statuses: Vec<u16>) -> (String, mpsc::Receiver<(Option<String>, String)>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/records", server.server_addr().to_ip().unwrap());
+        let (send, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            for status in statuses {
+                let mut request = server.recv().unwrap();
+                let auth = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Authorization"))
+                    .map(|h| h.value.to_string());
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let _ = send.send((auth, body));
+                request.respond(tiny_http::Response::empty(status)).unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    fn settings(url: String) -> WebhookSettings {
+        WebhookSettings {
+            enabled: true,
+            url,
+            token: Some("secret".into()),
+        }
+    }
+
+    fn enqueue(store: &Store, id: u64) {
+        let record = Record::new(id, 1, format!("B{id}"), Local::now(), "".into(), "".into());
+        store.enqueue_delivery(&record).unwrap();
+    }
+
+    /// Runs the webhook until the deliveries it picked up have come back.
+    fn settle(webhook: &mut Webhook, store: &Store, settings: &WebhookSettings) {
+        let start = Instant::now();
+        webhook.update(store, settings).unwrap();
+        while !webhook.in_flight.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5), "no outcome");
+            std::thread::sleep(Duration::from_millis(10));
+            webhook.update(store, settings).unwrap();
+        }
+    }
+
+    #[test]
+    fn success_delivers() {
+        let (url, received) = serve(vec![201]);
+        let settings = settings(url);
+        let store = Store::open_in_memory().unwrap();
+        enqueue(&store, 7);
+
+        let mut webhook = Webhook::start(None);
+        settle(&mut webhook, &store, &settings);
+
+        let (auth, body) = received.recv().unwrap();
+        assert_eq!(auth.as_deref(), Some("Bearer secret"));
+        assert!(body.contains("\"B7\""), "{body}");
+        assert_eq!(webhook.counts, (0, 0));
+    }
+
+    #[test]
+    fn timeouts_throttling_and_server_errors_are_retried_later() {
+        for status in [408, 429, 500, 503] {
+            let (url, _received) = serve(vec![status]);
+            let settings = settings(url);
+            let store = Store::open_in_memory().unwrap();
+            enqueue(&store, 1);
+
+            let mut webhook = Webhook::start(None);
+            settle(&mut webhook, &store, &settings);
+
+            assert_eq!(webhook.counts, (1, 0), "HTTP {status}");
+            assert!(
+                store.due_deliveries(BATCH).unwrap().is_empty(),
+                "HTTP {status}"
+            );
+        }
+    }
+
+    #[test]
+    fn unreachable_server_is_retried_later() {
+        // Bind and drop to find a port nobody listens on.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let settings = settings(format!("http://127.0.0.1:{port}/records"));
+        let store = Store::open_in_memory().unwrap();
+        enqueue(&store, 1);
+
+        let mut webhook = Webhook::start(None);
+        settle(&mut webhook, &store, &settings);
+
+        assert_eq!(webhook.counts, (1, 0));
+        assert!(store.due_deliveries(BATCH).unwrap().is_empty());
+    }
+
+    #[test]
+    fn other_client_errors_fail() {
+        for status in [400, 401, 404, 422] {
+            let (url, _received) = serve(vec![status]);
+            let settings = settings(url);
+            let store = Store::open_in_memory().unwrap();
+            enqueue(&store, 1);
+
+            let mut webhook = Webhook::start(None);
+            settle(&mut webhook, &store, &settings);
+
+            assert_eq!(webhook.counts, (0, 1), "HTTP {status}");
+
+            webhook.retry_failed(&store).unwrap();
+            assert_eq!(store.due_deliveries(BATCH).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        assert_eq!(backoff(0).num_seconds(), 1);
+        assert_eq!(backoff(3).num_seconds(), 8);
+        assert_eq!(backoff(20).num_seconds(), MAX_BACKOFF_SECS as i64);
+        assert_eq!(backoff(u32::MAX).num_seconds(), MAX_BACKOFF_SECS as i64);
+    }
+
+    #[test]
+    fn outbox_survives_a_restart() {
+        let path = std::env::temp_dir()
+            .join(format!("sn-tracer-test-{}", std::process::id()))
+            .join("outbox.db");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let store = Store::open(&path).unwrap();
+            enqueue(&store, 1);
+            enqueue(&store, 2);
+            // Closed before the webhook got to post anything.
+            let mut webhook = Webhook::start(None);
+            webhook.update(&store, &WebhookSettings::default()).unwrap();
+        }
+
+        let (url, received) = serve(vec![200, 200]);
+        let settings = settings(url);
+        let store = Store::open(&path).unwrap();
+        assert_eq!(store.delivery_counts().unwrap(), (2, 0));
+
+        let mut webhook = Webhook::start(None);
+        settle(&mut webhook, &store, &settings);
+
+        assert_eq!(webhook.counts, (0, 0));
+        let bodies: Vec<_> = received.try_iter().map(|(_, body)| body).collect();
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies[0].contains("\"B1\"") && bodies[1].contains("\"B2\""));
+    }
+}